@@ -0,0 +1,59 @@
+//! Compares resampling 48kHz capture callbacks one buffer at a time with
+//! `audio_resample` against the stateful `Resampler` kept per stream.
+//!
+//! Run with `cargo bench --bench resample`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use peeches_lib::audio::audio_resample;
+use peeches_lib::audio::format::{ResampleQuality, Resampler};
+
+const SAMPLE_RATE: u32 = 48000;
+// 10ms, a typical WASAPI callback
+const CHUNK: usize = 480;
+
+fn one_second() -> Vec<f32> {
+    (0..SAMPLE_RATE as usize)
+        .map(|i| (i as f32 / SAMPLE_RATE as f32 * 440.0 * std::f32::consts::TAU).sin() * 0.5)
+        .collect()
+}
+
+fn resample(c: &mut Criterion) {
+    let audio = one_second();
+    let mut group = c.benchmark_group("resample 1s of 48kHz in 10ms chunks");
+    group.throughput(Throughput::Elements(audio.len() as u64));
+    group.sample_size(20);
+
+    group.bench_function("audio_resample per chunk (best)", |b| {
+        b.iter(|| {
+            audio
+                .chunks(CHUNK)
+                .map(|chunk| audio_resample(chunk, SAMPLE_RATE, 16000, 1).len())
+                .sum::<usize>()
+        })
+    });
+
+    for quality in [
+        ResampleQuality::Best,
+        ResampleQuality::Medium,
+        ResampleQuality::Fastest,
+        ResampleQuality::Linear,
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new("stateful Resampler", format!("{quality:?}")),
+            &quality,
+            |b, &quality| {
+                let mut resampler = Resampler::new(SAMPLE_RATE, 16000, quality).unwrap();
+                b.iter(|| {
+                    audio
+                        .chunks(CHUNK)
+                        .map(|chunk| resampler.process(chunk).len())
+                        .sum::<usize>()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, resample);
+criterion_main!(benches);
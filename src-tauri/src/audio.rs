@@ -1,60 +1,616 @@
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    mpsc, Arc, Mutex,
-};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 
 use ringbuffer::{AllocRingBuffer, RingBuffer};
+use serde::{Deserialize, Serialize};
 
-pub struct AudioOutput {
+use crate::vad::{Vad, FRAME_SAMPLES};
+use format::ResampleQuality;
+
+/// Something that can feed 16kHz mono samples into the transcription pipeline.
+pub trait AudioSource {
+    fn start_recording(&self) -> anyhow::Result<()>;
+    fn stop_recording(&self);
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AudioSourceConfig {
+    /// The platform's system audio capture
+    System,
+    /// A recorded WAV/FLAC/MP3 file
+    File {
+        path: PathBuf,
+        /// Play at real-time speed instead of as fast as possible
+        #[serde(default)]
+        realtime: bool,
+    },
+}
+
+/// Whose voice a stream of audio carries.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Track {
+    /// The local microphone
+    Me,
+    /// System audio, i.e. the remote side of a call
+    #[default]
+    Them,
+    /// Microphone and system audio mixed together
+    Mixed,
+}
+
+impl Track {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Track::Me => "me",
+            Track::Them => "them",
+            Track::Mixed => "mixed",
+        }
+    }
+}
+
+impl FromStr for Track {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "me" => Ok(Track::Me),
+            "them" => Ok(Track::Them),
+            "mixed" => Ok(Track::Mixed),
+            _ => Err(format!("unknown track '{s}'")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// System audio only
+    #[default]
+    System,
+    /// Microphone and system audio as separate tracks
+    Dual,
+    /// Microphone and system audio mixed into one track
+    Mixed,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    /// A playback device, captured through loopback
+    Output,
+    /// The monitor of a playback device
+    Monitor,
+    /// A microphone or line in
+    Input,
+}
+
+/// A device the system source can capture from.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioDevice {
+    /// Stays the same across restarts and reconnects, used to persist the choice
+    pub id: String,
+    pub name: String,
+    pub kind: DeviceKind,
+    /// What gets captured when no device is selected
+    pub is_default: bool,
+}
+
+/// Enumerates the capture devices of the current platform.
+pub fn list_devices() -> anyhow::Result<Vec<AudioDevice>> {
     #[cfg(target_os = "macos")]
-    inner: macos::MacAudioOutput,
+    {
+        macos::list_devices()
+    }
     #[cfg(target_os = "windows")]
-    inner: win::WinAudioOutput,
+    {
+        win::list_devices()
+    }
+    #[cfg(target_os = "linux")]
+    {
+        linux::list_devices()
+    }
 }
 
+/// A running application whose audio can be captured on its own.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureTarget {
+    /// Stays the same across restarts of the application, used to persist the choice
+    pub id: String,
+    pub name: String,
+    pub pid: Option<u32>,
+}
+
+/// Enumerates the applications that can be captured on their own.
+pub fn list_targets() -> anyhow::Result<Vec<CaptureTarget>> {
+    #[cfg(target_os = "macos")]
+    {
+        macos::list_targets()
+    }
+    #[cfg(target_os = "windows")]
+    {
+        // cpal only offers loopback of a whole device
+        Ok(Vec::new())
+    }
+    #[cfg(target_os = "linux")]
+    {
+        linux::list_targets()
+    }
+}
+
+/// Re-transcribe the running utterance this often while speech continues
+const UPDATE_INTERVAL: usize = 16000 * 6 / 10;
+/// Whisper rejects input shorter than a second, so windows are padded to this
+const MIN_WINDOW: usize = 16000 * 11 / 10;
+/// Utterances are cut here even without a pause
+const MAX_UTTERANCE: usize = 16000 * 15;
+/// Utterances shorter than this are treated as clicks and dropped
+const MIN_UTTERANCE: usize = 16000 * 3 / 10;
+/// Audio kept from before the detector fires so word onsets aren't clipped
+const PRE_ROLL: usize = 16000 / 5;
+
+/// A window of 16kHz mono samples handed to whisper.
+pub struct AudioWindow {
+    pub samples: Vec<f32>,
+    /// Offset in samples of the first speech sample since recording started
+    pub start: usize,
+    /// Offset in samples of the window's end since recording started
+    pub end: usize,
+    /// Set once the utterance has ended; earlier windows of it are previews
+    pub is_final: bool,
+    pub track: Track,
+    /// History session the audio was recorded in, none for audio from a file
+    pub session_id: Option<i64>,
+}
+
+/// Splits a whole recording into utterances the way live capture does, without
+/// the previews of utterances in progress.
+pub fn split_utterances(samples: &[f32], track: Track) -> Vec<AudioWindow> {
+    let mut splitter = UtteranceSplitter::new(track);
+    let mut windows: Vec<AudioWindow> = samples
+        .chunks(FRAME_SAMPLES)
+        .flat_map(|frame| splitter.push(frame))
+        .collect();
+    windows.extend(splitter.finish());
+    windows
+}
+
+/// Splits a stream into utterances the way live capture does, handing out
+/// only the final window of each.
+pub struct UtteranceSplitter(Chunker);
+
+impl UtteranceSplitter {
+    pub fn new(track: Track) -> Self {
+        Self(Chunker::new(track))
+    }
+
+    /// Takes 16kHz mono samples of any length.
+    pub fn push(&mut self, samples: &[f32]) -> Vec<AudioWindow> {
+        let mut windows = self.0.push(samples.to_vec());
+        windows.retain(|window| window.is_final);
+        windows
+    }
+
+    /// Closes the utterance in progress at the end of the stream.
+    pub fn finish(&mut self) -> Option<AudioWindow> {
+        self.0.finish()
+    }
+}
+
+/// Groups speech frames into utterances and drops the silence between them.
+struct Chunker {
+    track: Track,
+    session_id: Option<i64>,
+    vad: Vad,
+    pending: Vec<f32>,
+    pre_roll: AllocRingBuffer<f32>,
+    utterance: Vec<f32>,
+    utterance_start: usize,
+    since_update: usize,
+    position: usize,
+}
+
+impl Chunker {
+    fn new(track: Track) -> Self {
+        Self {
+            track,
+            session_id: None,
+            vad: Vad::default(),
+            pending: Vec::with_capacity(FRAME_SAMPLES),
+            pre_roll: AllocRingBuffer::new(PRE_ROLL),
+            utterance: Vec::new(),
+            utterance_start: 0,
+            since_update: 0,
+            position: 0,
+        }
+    }
+
+    fn push(&mut self, data: Vec<f32>) -> Vec<AudioWindow> {
+        let mut windows = Vec::new();
+        self.pending.extend(data);
+        let frames = self.pending.len() / FRAME_SAMPLES;
+        let rest = self.pending.split_off(frames * FRAME_SAMPLES);
+        let pending = std::mem::replace(&mut self.pending, rest);
+        for frame in pending.chunks(FRAME_SAMPLES) {
+            self.position += frame.len();
+            if !self.vad.process(frame) {
+                windows.extend(self.finish());
+                self.pre_roll.extend(frame.iter().copied());
+                continue;
+            }
+            if self.utterance.is_empty() {
+                self.utterance_start = self.position - frame.len() - self.pre_roll.len();
+                self.utterance.extend(self.pre_roll.to_vec());
+                self.pre_roll.clear();
+                self.since_update = 0;
+            }
+            self.utterance.extend_from_slice(frame);
+            self.since_update += frame.len();
+            if self.utterance.len() >= MAX_UTTERANCE {
+                windows.extend(self.finish());
+            } else if self.since_update >= UPDATE_INTERVAL && self.utterance.len() >= MIN_WINDOW {
+                self.since_update = 0;
+                windows.push(self.window(self.utterance.clone(), false));
+            }
+        }
+        windows
+    }
+
+    /// Closes the current utterance, if any.
+    fn finish(&mut self) -> Option<AudioWindow> {
+        if self.utterance.is_empty() {
+            return None;
+        }
+        let samples = std::mem::take(&mut self.utterance);
+        if samples.len() < MIN_UTTERANCE {
+            return None;
+        }
+        Some(self.window(samples, true))
+    }
+
+    fn window(&self, mut samples: Vec<f32>, is_final: bool) -> AudioWindow {
+        let start = self.utterance_start;
+        let end = start + samples.len();
+        if samples.len() < MIN_WINDOW {
+            samples.resize(MIN_WINDOW, 0.0);
+        }
+        AudioWindow {
+            samples,
+            start,
+            end,
+            is_final,
+            track: self.track,
+            session_id: self.session_id,
+        }
+    }
+}
+
+/// Lines up the microphone and system streams sample by sample and sums them.
+#[derive(Default)]
+struct Mixer {
+    pending: [VecDeque<f32>; 2],
+}
+
+impl Mixer {
+    /// A stream running ahead by more than this is not waited for, so a stalled
+    /// device can't hold back the other one.
+    const MAX_LAG: usize = 16000 / 2;
+
+    fn push(&mut self, input: usize, data: Vec<f32>) -> Vec<f32> {
+        self.pending[input].extend(data);
+        let [a, b] = &mut self.pending;
+        let both = a.len().min(b.len());
+        let mut mixed: Vec<f32> = a
+            .drain(..both)
+            .zip(b.drain(..both))
+            .map(|(a, b)| (a + b).clamp(-1.0, 1.0))
+            .collect();
+        for pending in &mut self.pending {
+            if pending.len() > Self::MAX_LAG {
+                let excess = pending.len() - Self::MAX_LAG;
+                mixed.extend(pending.drain(..excess));
+            }
+        }
+        mixed
+    }
+}
+
+/// How the system source captures.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureSettings {
+    /// Capture device id, `None` follows the system default
+    pub device: Option<String>,
+    /// Application to capture on its own, `None` captures everything played
+    pub target: Option<String>,
+    pub quality: ResampleQuality,
+    pub mode: CaptureMode,
+    /// Input device id of the microphone in dual and mixed mode, `None`
+    /// follows the system default
+    pub mic_device: Option<String>,
+}
+
+pub struct AudioOutput {
+    sender: mpsc::Sender<AudioWindow>,
+    /// Chunkers of the system (or mixed) and the microphone track
+    chunkers: [Arc<Mutex<Chunker>>; 2],
+    mixer: Arc<Mutex<Mixer>>,
+    inner: Box<dyn AudioSource>,
+    /// The microphone, captured unless the mode is system audio only
+    mic: Option<Box<dyn AudioSource>>,
+    config: AudioSourceConfig,
+    settings: CaptureSettings,
+    recording: AtomicBool,
+    tap: Arc<Mutex<Option<Tap>>>,
+}
+
+/// Receives every chunk of 16kHz audio along with the track it belongs to.
+pub type Tap = Box<dyn Fn(Track, &[f32]) + Send>;
+
 unsafe impl Send for AudioOutput {}
 unsafe impl Sync for AudioOutput {}
 
+const SYSTEM: usize = 0;
+const MIC: usize = 1;
+
 impl AudioOutput {
-    pub fn new(sender: mpsc::Sender<Vec<f32>>) -> anyhow::Result<Self> {
-        let speech_buf = Arc::new(Mutex::new(AllocRingBuffer::new(16000 * 3)));
-        let counter = Arc::new(AtomicUsize::new(0));
-        let cb = Box::new(move |data| {
-            let mut buf = speech_buf.lock().unwrap();
-            buf.extend(data);
-            counter.fetch_add(1, Ordering::SeqCst);
-            if counter.load(Ordering::SeqCst) > (16000.0 / 320.0 * 0.6) as usize
-                && buf.len() as f64 > 1.1 * 16000.0
-            {
-                let samples = buf.to_vec();
-                drop(buf);
-                sender.send(samples).unwrap();
-                counter.store(0, Ordering::SeqCst);
+    pub fn new(
+        sender: mpsc::Sender<AudioWindow>,
+        settings: CaptureSettings,
+    ) -> anyhow::Result<Self> {
+        let mut output = Self {
+            sender,
+            chunkers: [
+                Arc::new(Mutex::new(Chunker::new(Track::Them))),
+                Arc::new(Mutex::new(Chunker::new(Track::Me))),
+            ],
+            mixer: Arc::default(),
+            inner: Box::new(NullSource),
+            mic: None,
+            config: AudioSourceConfig::System,
+            settings: CaptureSettings::default(),
+            recording: AtomicBool::new(false),
+            tap: Arc::default(),
+        };
+        if let Err(e) = output.set_capture_settings(settings.clone()) {
+            log::warn!(
+                "failed to capture with {:?}, using defaults: {}",
+                settings,
+                e
+            );
+            output.set_capture_settings(CaptureSettings {
+                device: None,
+                target: None,
+                mode: CaptureMode::System,
+                ..settings
+            })?;
+        }
+        Ok(output)
+    }
+
+    /// Splits the incoming stream into utterances for whisper, skipping silence.
+    fn on_data(&self, chunker: usize) -> Box<dyn Fn(Vec<f32>) + Send> {
+        let sender = self.sender.clone();
+        let chunker = self.chunkers[chunker].clone();
+        let tap = self.tap.clone();
+        Box::new(move |data| {
+            let windows = {
+                let mut chunker = chunker.lock().unwrap();
+                if let Some(tap) = tap.lock().unwrap().as_ref() {
+                    tap(chunker.track, &data);
+                }
+                chunker.push(data)
+            };
+            for window in windows {
+                if sender.send(window).is_err() {
+                    return;
+                }
             }
-        });
-        #[cfg(target_os = "macos")]
-        {
-            Ok(Self {
-                inner: macos::MacAudioOutput::new(cb),
-            })
+        })
+    }
+
+    /// Callback for one input of the mixer, feeding the mix to the system chunker.
+    fn on_mixed_data(&self, input: usize) -> Box<dyn Fn(Vec<f32>) + Send> {
+        let mixer = self.mixer.clone();
+        let on_data = self.on_data(SYSTEM);
+        Box::new(move |data| {
+            let mixed = mixer.lock().unwrap().push(input, data);
+            if !mixed.is_empty() {
+                on_data(mixed);
+            }
+        })
+    }
+
+    fn callbacks(
+        &self,
+        mode: CaptureMode,
+    ) -> (Box<dyn Fn(Vec<f32>) + Send>, Box<dyn Fn(Vec<f32>) + Send>) {
+        match mode {
+            CaptureMode::Mixed => (self.on_mixed_data(SYSTEM), self.on_mixed_data(MIC)),
+            _ => (self.on_data(SYSTEM), self.on_data(MIC)),
         }
-        #[cfg(target_os = "windows")]
-        {
-            Ok(Self {
-                inner: win::WinAudioOutput::new(cb)?,
-            })
+    }
+
+    fn system_track(mode: CaptureMode) -> Track {
+        match mode {
+            CaptureMode::Mixed => Track::Mixed,
+            _ => Track::Them,
+        }
+    }
+
+    pub fn set_source(&mut self, config: &AudioSourceConfig) -> anyhow::Result<()> {
+        match config {
+            AudioSourceConfig::System => {
+                self.config = config.clone();
+                self.set_capture_settings(self.settings.clone())
+            }
+            AudioSourceConfig::File { path, realtime } => {
+                let on_data = self.on_data(SYSTEM);
+                let source = file::FileAudioSource::open(path, *realtime, on_data)?;
+                self.config = config.clone();
+                self.chunkers[SYSTEM].lock().unwrap().track = Track::Them;
+                self.replace_sources(Box::new(source), None)
+            }
         }
     }
 
-    pub fn start_recording(&self) -> anyhow::Result<()> {
-        self.inner.start_recording()
+    /// Sets where captured audio is copied to, e.g. to keep it on disk.
+    pub fn set_tap(&self, tap: Option<Tap>) {
+        *self.tap.lock().unwrap() = tap;
+    }
+
+    pub fn settings(&self) -> &CaptureSettings {
+        &self.settings
+    }
+
+    /// Applies new capture settings to the system source, carrying on
+    /// recording if it was.
+    pub fn set_capture_settings(&mut self, settings: CaptureSettings) -> anyhow::Result<()> {
+        if matches!(self.config, AudioSourceConfig::System) {
+            let (on_system, on_mic) = self.callbacks(settings.mode);
+            let system = system_source(on_system, &settings)?;
+            let mic = match settings.mode {
+                CaptureMode::System => None,
+                _ => Some(mic_source(on_mic, &settings)?),
+            };
+            self.chunkers[SYSTEM].lock().unwrap().track = Self::system_track(settings.mode);
+            self.replace_sources(system, mic)?;
+        }
+        self.settings = settings;
+        Ok(())
+    }
+
+    fn replace_sources(
+        &mut self,
+        source: Box<dyn AudioSource>,
+        mic: Option<Box<dyn AudioSource>>,
+    ) -> anyhow::Result<()> {
+        self.stop_sources();
+        self.inner = source;
+        self.mic = mic;
+        if self.recording.load(Ordering::SeqCst) {
+            self.start_sources()?;
+        }
+        Ok(())
+    }
+
+    fn start_sources(&self) -> anyhow::Result<()> {
+        self.inner.start_recording()?;
+        if let Some(mic) = &self.mic {
+            mic.start_recording()?;
+        }
+        Ok(())
+    }
+
+    fn stop_sources(&self) {
+        self.inner.stop_recording();
+        if let Some(mic) = &self.mic {
+            mic.stop_recording();
+        }
+    }
+
+    /// Starts capturing for a history session, with window offsets counted
+    /// from this call.
+    pub fn start_recording(&mut self, session_id: i64) -> anyhow::Result<()> {
+        // The default device may have changed since the source was created
+        if matches!(self.config, AudioSourceConfig::System) && self.settings.device.is_none() {
+            self.set_capture_settings(self.settings.clone())?;
+        }
+        let track = self.chunkers[SYSTEM].lock().unwrap().track;
+        *self.chunkers[SYSTEM].lock().unwrap() = Chunker {
+            session_id: Some(session_id),
+            ..Chunker::new(track)
+        };
+        *self.chunkers[MIC].lock().unwrap() = Chunker {
+            session_id: Some(session_id),
+            ..Chunker::new(Track::Me)
+        };
+        *self.mixer.lock().unwrap() = Mixer::default();
+        self.start_sources()?;
+        self.recording.store(true, Ordering::SeqCst);
+        Ok(())
     }
 
+    /// Stops capturing and flushes utterances still in progress.
     pub fn stop_recording(&self) {
-        self.inner.stop_recording()
+        self.recording.store(false, Ordering::SeqCst);
+        self.stop_sources();
+        for chunker in &self.chunkers {
+            if let Some(window) = chunker.lock().unwrap().finish() {
+                let _ = self.sender.send(window);
+            }
+        }
     }
 }
 
+/// Placeholder until the first real source is set up.
+struct NullSource;
+
+impl AudioSource for NullSource {
+    fn start_recording(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn stop_recording(&self) {}
+}
+
+fn system_source(
+    on_data: Box<dyn Fn(Vec<f32>) + Send>,
+    settings: &CaptureSettings,
+) -> anyhow::Result<Box<dyn AudioSource>> {
+    #[cfg(target_os = "macos")]
+    {
+        Ok(Box::new(macos::MacAudioOutput::new(on_data, settings)?))
+    }
+    #[cfg(target_os = "windows")]
+    {
+        Ok(Box::new(win::WinAudioOutput::new(on_data, settings)?))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        match settings.target.as_deref() {
+            Some(target) => Ok(Box::new(linux::LinuxAudioOutput::new_app(on_data, target)?)),
+            None => Ok(Box::new(linux::LinuxAudioOutput::new(
+                on_data,
+                settings.device.as_deref(),
+            )?)),
+        }
+    }
+}
+
+/// The selected microphone, or the default one.
+fn mic_source(
+    on_data: Box<dyn Fn(Vec<f32>) + Send>,
+    settings: &CaptureSettings,
+) -> anyhow::Result<Box<dyn AudioSource>> {
+    #[cfg(target_os = "macos")]
+    {
+        Ok(Box::new(macos::MacAudioOutput::new_mic(on_data, settings)?))
+    }
+    #[cfg(target_os = "windows")]
+    {
+        Ok(Box::new(win::WinAudioOutput::new_mic(on_data, settings)?))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let device = settings.mic_device.as_deref();
+        Ok(Box::new(linux::LinuxAudioOutput::new(
+            on_data,
+            Some(device.unwrap_or(linux::DEFAULT_SOURCE)),
+        )?))
+    }
+}
+
+/// Decodes a WAV/FLAC/MP3 file into 16kHz mono samples.
+pub fn decode_file(path: &Path) -> anyhow::Result<Vec<f32>> {
+    file::decode(path)
+}
+
 #[cfg(target_os = "windows")]
 mod win {
     use std::sync::atomic::AtomicBool;
@@ -65,33 +621,134 @@ mod win {
     use cpal::traits::HostTrait;
     use cpal::traits::StreamTrait;
 
-    use super::audio_resample;
-    use super::stereo_to_mono;
+    use super::format::{Converter, Sample, StreamFormat};
+    use super::{AudioDevice, AudioSource, CaptureSettings, DeviceKind};
+
+    // cpal has no persistent device ids, the kind prefixed name is the most stable thing
+    fn device_id(kind: DeviceKind, name: &str) -> String {
+        match kind {
+            DeviceKind::Input => format!("input:{name}"),
+            _ => format!("output:{name}"),
+        }
+    }
+
+    pub fn list_devices() -> anyhow::Result<Vec<AudioDevice>> {
+        let host = cpal::default_host();
+        let default_output = host.default_output_device().and_then(|d| d.name().ok());
+        let default = device_id(
+            DeviceKind::Output,
+            default_output.as_deref().unwrap_or_default(),
+        );
+        let outputs = host
+            .output_devices()?
+            .map(|device| (DeviceKind::Output, device));
+        let inputs = host
+            .input_devices()?
+            .map(|device| (DeviceKind::Input, device));
+        let mut devices = Vec::new();
+        for (kind, device) in outputs.chain(inputs) {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            let id = device_id(kind, &name);
+            devices.push(AudioDevice {
+                is_default: id == default,
+                id,
+                name,
+                kind,
+            });
+        }
+        Ok(devices)
+    }
+
+    fn find_device(host: &cpal::Host, id: &str) -> anyhow::Result<(cpal::Device, DeviceKind)> {
+        let outputs = host
+            .output_devices()?
+            .map(|device| (device, DeviceKind::Output));
+        let inputs = host
+            .input_devices()?
+            .map(|device| (device, DeviceKind::Input));
+        outputs
+            .chain(inputs)
+            .find(|(device, kind)| {
+                device
+                    .name()
+                    .is_ok_and(|name| device_id(*kind, &name) == id)
+            })
+            .ok_or_else(|| anyhow::Error::msg(format!("Audio device '{id}' not found")))
+    }
 
     pub struct WinAudioOutput {
         stream: cpal::Stream,
     }
 
     impl WinAudioOutput {
-        pub fn new(on_data: Box<dyn Fn(Vec<f32>) + Send>) -> anyhow::Result<Self> {
-            let err_fn = move |err| {
-                eprintln!("an error occurred on stream: {}", err);
+        pub fn new(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            settings: &CaptureSettings,
+        ) -> anyhow::Result<Self> {
+            if settings.target.is_some() {
+                anyhow::bail!("Capturing a single application is not supported on Windows");
+            }
+            let host = cpal::default_host();
+            let (device, kind) = match settings.device.as_deref() {
+                Some(id) => find_device(&host, id)?,
+                None => (
+                    host.default_output_device()
+                        .ok_or_else(|| anyhow::Error::msg("No output device found"))?,
+                    DeviceKind::Output,
+                ),
             };
+            Self::open(&device, kind, settings, on_data)
+        }
+
+        /// Captures the selected input device, or the default one.
+        pub fn new_mic(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            settings: &CaptureSettings,
+        ) -> anyhow::Result<Self> {
             let host = cpal::default_host();
-            let device = host.default_output_device().unwrap();
-            let config = device.default_output_config().unwrap();
-            let stream = match config.sample_format() {
-                cpal::SampleFormat::F32 => device.build_input_stream::<f32, _, _>(
-                    &config.into(),
-                    move |data, _: &_| {
-                        // TODO: assume 2 channels
-                        let mut resampled: Vec<f32> = audio_resample(data, 48000, 16000, 2);
-                        resampled = stereo_to_mono(&resampled).unwrap();
-                        on_data(resampled);
-                    },
-                    err_fn,
-                    None,
-                )?,
+            let device = match settings.mic_device.as_deref() {
+                Some(id) => match find_device(&host, id)? {
+                    (device, DeviceKind::Input) => device,
+                    _ => anyhow::bail!("Audio device '{id}' is not an input"),
+                },
+                None => host
+                    .default_input_device()
+                    .ok_or_else(|| anyhow::Error::msg("No input device found"))?,
+            };
+            Self::open(&device, DeviceKind::Input, settings, on_data)
+        }
+
+        fn open(
+            device: &cpal::Device,
+            kind: DeviceKind,
+            settings: &CaptureSettings,
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+        ) -> anyhow::Result<Self> {
+            // Building an input stream on an output device captures it in loopback
+            let config = match kind {
+                DeviceKind::Input => device.default_input_config()?,
+                _ => device.default_output_config()?,
+            };
+            let format = StreamFormat {
+                sample_rate: config.sample_rate().0,
+                channels: config.channels(),
+            };
+            log::info!("capturing {:?} {:?}", config.sample_format(), format);
+            let sample_format = config.sample_format();
+            let config = config.into();
+            let converter = Converter::new(format, settings.quality)?;
+            let stream = match sample_format {
+                cpal::SampleFormat::F32 => {
+                    build_stream::<f32>(device, &config, converter, on_data)?
+                }
+                cpal::SampleFormat::I16 => {
+                    build_stream::<i16>(device, &config, converter, on_data)?
+                }
+                cpal::SampleFormat::U16 => {
+                    build_stream::<u16>(device, &config, converter, on_data)?
+                }
                 sample_format => {
                     return Err(anyhow::Error::msg(format!(
                         "Unsupported sample format '{sample_format}'"
@@ -100,19 +757,417 @@ mod win {
             };
             Ok(WinAudioOutput { stream })
         }
+    }
+
+    fn build_stream<S: cpal::SizedSample + Sample>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut converter: Converter,
+        on_data: Box<dyn Fn(Vec<f32>) + Send>,
+    ) -> anyhow::Result<cpal::Stream> {
+        let stream = device.build_input_stream::<S, _, _>(
+            config,
+            move |data, _: &_| on_data(converter.process(data)),
+            |err| log::error!("an error occurred on stream: {}", err),
+            None,
+        )?;
+        Ok(stream)
+    }
 
-        pub fn start_recording(&self) -> anyhow::Result<()> {
+    impl AudioSource for WinAudioOutput {
+        fn start_recording(&self) -> anyhow::Result<()> {
             self.stream.play()?;
 
             Ok(())
         }
 
-        pub fn stop_recording(&self) {
+        fn stop_recording(&self) {
             self.stream.pause();
         }
     }
 }
 
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use libpulse_binding::callbacks::ListResult;
+    use libpulse_binding::context::{self, Context, FlagSet};
+    use libpulse_binding::mainloop::standard::{IterateResult, Mainloop};
+    use libpulse_binding::operation::{self, Operation};
+    use libpulse_binding::proplist::{properties, Proplist};
+    use libpulse_binding::sample::{Format, Spec};
+    use libpulse_binding::stream::{self, Direction, PeekResult, Stream};
+    use libpulse_simple_binding::Simple;
+
+    use super::{AudioDevice, AudioSource, CaptureTarget, DeviceKind};
+
+    // PulseAudio (and pipewire-pulse) resolve this to the monitor of the default sink
+    const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+    // ...and this to the default input, usually the microphone
+    pub const DEFAULT_SOURCE: &str = "@DEFAULT_SOURCE@";
+    // Let the server convert to 16kHz mono f32 so no resampling is needed here
+    const SPEC: Spec = Spec {
+        format: Format::FLOAT32NE,
+        channels: 1,
+        rate: 16000,
+    };
+    // 20ms per read. `read` blocks until the buffer is full, so this bounds the
+    // capture latency, and it is exactly one VAD frame.
+    const FRAME_SAMPLES: usize = 320;
+    // How often to look for the stream of an application that isn't playing
+    const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+    // How long to wait for more data from a monitor stream
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    type OnData = Mutex<Box<dyn Fn(Vec<f32>) + Send>>;
+
+    /// Connects a context of the asynchronous API, which can do what the
+    /// simple one can't: introspection and monitoring a single stream.
+    fn connect_context() -> anyhow::Result<(Mainloop, Context)> {
+        let mut mainloop = Mainloop::new()
+            .ok_or_else(|| anyhow::Error::msg("Failed to create PulseAudio mainloop"))?;
+        let mut context = Context::new(&mainloop, "Peeches")
+            .ok_or_else(|| anyhow::Error::msg("Failed to create PulseAudio context"))?;
+        context
+            .connect(None, FlagSet::NOFLAGS, None)
+            .map_err(|e| anyhow::Error::msg(format!("Failed to connect to PulseAudio: {e}")))?;
+        loop {
+            iterate(&mut mainloop)?;
+            match context.get_state() {
+                context::State::Ready => break,
+                context::State::Failed | context::State::Terminated => {
+                    anyhow::bail!("Failed to connect to PulseAudio")
+                }
+                _ => {}
+            }
+        }
+        Ok((mainloop, context))
+    }
+
+    /// Lists PulseAudio sources, whose names are stable across reconnects.
+    /// Capturing a sink means capturing its monitor source.
+    pub fn list_devices() -> anyhow::Result<Vec<AudioDevice>> {
+        let (mut mainloop, mut context) = connect_context()?;
+
+        let defaults = Rc::new(RefCell::new((String::new(), String::new())));
+        let op = context.introspect().get_server_info({
+            let defaults = defaults.clone();
+            move |info| {
+                let sink = info.default_sink_name.as_deref().unwrap_or_default();
+                let source = info.default_source_name.as_deref().unwrap_or_default();
+                *defaults.borrow_mut() = (format!("{sink}.monitor"), source.to_string());
+            }
+        });
+        wait(&mut mainloop, &op)?;
+
+        let devices = Rc::new(RefCell::new(Vec::new()));
+        let op = context.introspect().get_source_info_list({
+            let devices = devices.clone();
+            let defaults = defaults.clone();
+            move |result| {
+                let ListResult::Item(info) = result else {
+                    return;
+                };
+                let Some(id) = info.name.as_deref() else {
+                    return;
+                };
+                let (default_monitor, default_source) = &*defaults.borrow();
+                let (kind, is_default) = match info.monitor_of_sink {
+                    Some(_) => (DeviceKind::Monitor, id == default_monitor),
+                    None => (DeviceKind::Input, id == default_source),
+                };
+                devices.borrow_mut().push(AudioDevice {
+                    id: id.to_string(),
+                    name: info.description.as_deref().unwrap_or(id).to_string(),
+                    kind,
+                    is_default,
+                });
+            }
+        });
+        wait(&mut mainloop, &op)?;
+        context.disconnect();
+        let devices = devices.take();
+        Ok(devices)
+    }
+
+    /// Lists applications by their sink inputs, so only applications that
+    /// currently have a playback stream open show up.
+    pub fn list_targets() -> anyhow::Result<Vec<CaptureTarget>> {
+        let (mut mainloop, mut context) = connect_context()?;
+        let targets = Rc::new(RefCell::new(Vec::<CaptureTarget>::new()));
+        let op = context.introspect().get_sink_input_info_list({
+            let targets = targets.clone();
+            move |result| {
+                let ListResult::Item(info) = result else {
+                    return;
+                };
+                let Some(id) = app_id(&info.proplist) else {
+                    return;
+                };
+                let mut targets = targets.borrow_mut();
+                // Browsers open a stream per tab
+                if targets.iter().any(|target| target.id == id) {
+                    return;
+                }
+                let proplist = &info.proplist;
+                targets.push(CaptureTarget {
+                    name: proplist
+                        .get_str(properties::APPLICATION_NAME)
+                        .unwrap_or_else(|| id.clone()),
+                    pid: proplist
+                        .get_str(properties::APPLICATION_PROCESS_ID)
+                        .and_then(|pid| pid.parse().ok()),
+                    id,
+                });
+            }
+        });
+        wait(&mut mainloop, &op)?;
+        context.disconnect();
+        let targets = targets.take();
+        Ok(targets)
+    }
+
+    /// Identifies the application behind a stream by its binary, which unlike
+    /// the sink input index survives the application reopening its stream.
+    fn app_id(proplist: &Proplist) -> Option<String> {
+        proplist
+            .get_str(properties::APPLICATION_PROCESS_BINARY)
+            .or_else(|| proplist.get_str(properties::APPLICATION_NAME))
+            .map(|app| format!("app:{app}"))
+    }
+
+    /// Finds the sink input an application plays on and the monitor of its sink.
+    fn find_sink_input(
+        mainloop: &mut Mainloop,
+        context: &Context,
+        target: &str,
+    ) -> anyhow::Result<Option<(u32, String)>> {
+        let found = Rc::new(RefCell::new(None));
+        let op = context.introspect().get_sink_input_info_list({
+            let found = found.clone();
+            let target = target.to_string();
+            move |result| {
+                let ListResult::Item(info) = result else {
+                    return;
+                };
+                if app_id(&info.proplist).as_deref() != Some(target.as_str()) {
+                    return;
+                }
+                let mut found = found.borrow_mut();
+                // Prefer a stream that is actually playing
+                if found.is_none() || !info.corked {
+                    *found = Some((info.index, info.sink));
+                }
+            }
+        });
+        wait(mainloop, &op)?;
+        let Some((index, sink)) = found.take() else {
+            return Ok(None);
+        };
+
+        let monitor = Rc::new(RefCell::new(None));
+        let op = context.introspect().get_sink_info_by_index(sink, {
+            let monitor = monitor.clone();
+            move |result| {
+                if let ListResult::Item(info) = result {
+                    *monitor.borrow_mut() = info.monitor_source_name.as_deref().map(str::to_string);
+                }
+            }
+        });
+        wait(mainloop, &op)?;
+        let monitor = monitor.take();
+        Ok(monitor.map(|monitor| (index, monitor)))
+    }
+
+    fn iterate(mainloop: &mut Mainloop) -> anyhow::Result<()> {
+        match mainloop.iterate(true) {
+            IterateResult::Success(_) => Ok(()),
+            IterateResult::Quit(_) | IterateResult::Err(_) => {
+                anyhow::bail!("PulseAudio mainloop stopped")
+            }
+        }
+    }
+
+    fn wait<G: ?Sized>(mainloop: &mut Mainloop, op: &Operation<G>) -> anyhow::Result<()> {
+        while op.get_state() == operation::State::Running {
+            iterate(mainloop)?;
+        }
+        Ok(())
+    }
+
+    fn to_samples(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    fn record_device(
+        simple: &Simple,
+        running: &AtomicBool,
+        on_data: &OnData,
+    ) -> anyhow::Result<()> {
+        let mut buf = vec![0u8; FRAME_SAMPLES * std::mem::size_of::<f32>()];
+        while running.load(Ordering::SeqCst) {
+            simple.read(&mut buf)?;
+            (on_data.lock().unwrap())(to_samples(&buf));
+        }
+        Ok(())
+    }
+
+    /// Records the sink input of an application through a monitor stream,
+    /// following it as the application closes and reopens its stream.
+    fn record_app(target: &str, running: &AtomicBool, on_data: &OnData) -> anyhow::Result<()> {
+        let (mut mainloop, mut context) = connect_context()?;
+        while running.load(Ordering::SeqCst) {
+            let Some((index, monitor)) = find_sink_input(&mut mainloop, &context, target)? else {
+                std::thread::sleep(RETRY_INTERVAL);
+                continue;
+            };
+            log::info!("capturing sink input {} of {}", index, target);
+            let mut stream = Stream::new(&mut context, "application audio", &SPEC, None)
+                .ok_or_else(|| anyhow::Error::msg("Failed to create PulseAudio stream"))?;
+            stream.set_monitor_stream(index)?;
+            stream.connect_record(
+                Some(&monitor),
+                None,
+                stream::FlagSet::DONT_MOVE | stream::FlagSet::ADJUST_LATENCY,
+            )?;
+            // Non-blocking so a paused application doesn't hold up stop_recording
+            while running.load(Ordering::SeqCst) {
+                if let IterateResult::Quit(_) | IterateResult::Err(_) = mainloop.iterate(false) {
+                    anyhow::bail!("PulseAudio mainloop stopped");
+                }
+                match stream.get_state() {
+                    stream::State::Ready => {}
+                    // The application closed its stream
+                    stream::State::Failed | stream::State::Terminated => break,
+                    _ => {
+                        std::thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                }
+                match stream.peek()? {
+                    PeekResult::Empty => std::thread::sleep(POLL_INTERVAL),
+                    PeekResult::Hole(_) => stream.discard()?,
+                    PeekResult::Data(data) => {
+                        let samples = to_samples(data);
+                        stream.discard()?;
+                        (on_data.lock().unwrap())(samples);
+                    }
+                }
+            }
+            let _ = stream.disconnect();
+        }
+        context.disconnect();
+        Ok(())
+    }
+
+    enum Source {
+        Device(String),
+        /// An application, by the id `list_targets` gives it
+        App(String),
+    }
+
+    pub struct LinuxAudioOutput {
+        on_data: Arc<OnData>,
+        source: Source,
+        running: Arc<AtomicBool>,
+        handle: Mutex<Option<JoinHandle<()>>>,
+    }
+
+    impl LinuxAudioOutput {
+        pub fn new(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            device: Option<&str>,
+        ) -> anyhow::Result<Self> {
+            let device = device.unwrap_or(DEFAULT_MONITOR).to_string();
+            // Fail early if there is no sound server to talk to
+            drop(Self::connect(&device)?);
+            Ok(Self::with_source(on_data, Source::Device(device)))
+        }
+
+        /// Captures only what one application plays. It doesn't have to be
+        /// playing yet, its stream is picked up once it starts.
+        pub fn new_app(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            target: &str,
+        ) -> anyhow::Result<Self> {
+            let (_, mut context) = connect_context()?;
+            context.disconnect();
+            Ok(Self::with_source(on_data, Source::App(target.to_string())))
+        }
+
+        fn with_source(on_data: Box<dyn Fn(Vec<f32>) + Send>, source: Source) -> Self {
+            Self {
+                on_data: Arc::new(Mutex::new(on_data)),
+                source,
+                running: Arc::new(AtomicBool::new(false)),
+                handle: Mutex::new(None),
+            }
+        }
+
+        fn connect(device: &str) -> anyhow::Result<Simple> {
+            Simple::new(
+                None,
+                "Peeches",
+                Direction::Record,
+                Some(device),
+                "system audio",
+                &SPEC,
+                None,
+                None,
+            )
+            .map_err(|e| anyhow::Error::msg(format!("Failed to connect to PulseAudio: {e}")))
+        }
+    }
+
+    impl AudioSource for LinuxAudioOutput {
+        fn start_recording(&self) -> anyhow::Result<()> {
+            let mut handle = self.handle.lock().unwrap();
+            if handle.as_ref().is_some_and(|h| !h.is_finished()) {
+                return Ok(());
+            }
+            let on_data = self.on_data.clone();
+            let record: Box<dyn FnOnce(&AtomicBool) -> anyhow::Result<()> + Send> = match &self
+                .source
+            {
+                Source::Device(device) => {
+                    let simple = Self::connect(device)?;
+                    Box::new(move |running: &AtomicBool| record_device(&simple, running, &on_data))
+                }
+                Source::App(target) => {
+                    let target = target.clone();
+                    Box::new(move |running: &AtomicBool| record_app(&target, running, &on_data))
+                }
+            };
+            let running = self.running.clone();
+            running.store(true, Ordering::SeqCst);
+            handle.replace(std::thread::spawn(move || {
+                if let Err(e) = record(&running) {
+                    log::error!("an error occurred on stream: {}", e);
+                }
+                running.store(false, Ordering::SeqCst);
+            }));
+            log::info!("stream started");
+            Ok(())
+        }
+
+        fn stop_recording(&self) {
+            self.running.store(false, Ordering::SeqCst);
+            if let Some(handle) = self.handle.lock().unwrap().take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
 pub fn audio_resample(
     data: &[f32],
     sample_rate0: u32,
@@ -130,16 +1185,199 @@ pub fn audio_resample(
     .unwrap_or_default()
 }
 
-#[allow(dead_code)]
-pub fn stereo_to_mono(stereo_data: &[f32]) -> anyhow::Result<Vec<f32>> {
-    let mut mono_data = Vec::with_capacity(stereo_data.len() / 2);
+/// Conversion of whatever a capture device delivers into the 16kHz mono f32
+/// stream the pipeline expects. Platform independent so every backend shares it.
+pub mod format {
+    use samplerate::{ConverterType, Samplerate};
+    use serde::{Deserialize, Serialize};
+
+    pub const TARGET_SAMPLE_RATE: u32 = 16000;
+
+    /// Layout of the interleaved buffers a capture callback receives.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct StreamFormat {
+        pub sample_rate: u32,
+        pub channels: u16,
+    }
+
+    /// A raw sample type that can be normalized to [-1.0, 1.0].
+    pub trait Sample: Copy {
+        fn to_f32(self) -> f32;
+    }
+
+    impl Sample for f32 {
+        fn to_f32(self) -> f32 {
+            self
+        }
+    }
+
+    impl Sample for i16 {
+        fn to_f32(self) -> f32 {
+            self as f32 / 32768.0
+        }
+    }
+
+    impl Sample for u16 {
+        fn to_f32(self) -> f32 {
+            (self as f32 - 32768.0) / 32768.0
+        }
+    }
+
+    /// Averages each interleaved frame of `channels` samples into one.
+    pub fn downmix<S: Sample>(data: &[S], channels: u16) -> Vec<f32> {
+        let channels = channels.max(1) as usize;
+        data.chunks_exact(channels)
+            .map(|frame| frame.iter().map(|s| s.to_f32()).sum::<f32>() / channels as f32)
+            .collect()
+    }
 
-    for chunk in stereo_data.chunks_exact(2) {
-        let average = (chunk[0] + chunk[1]) / 2.0;
-        mono_data.push(average);
+    #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ResampleQuality {
+        Best,
+        #[default]
+        Medium,
+        Fastest,
+        Linear,
     }
 
-    Ok(mono_data)
+    impl ResampleQuality {
+        fn converter_type(self) -> ConverterType {
+            match self {
+                ResampleQuality::Best => ConverterType::SincBestQuality,
+                ResampleQuality::Medium => ConverterType::SincMediumQuality,
+                ResampleQuality::Fastest => ConverterType::SincFastest,
+                ResampleQuality::Linear => ConverterType::Linear,
+            }
+        }
+    }
+
+    /// Mono resampler that keeps its filter state between calls, so consecutive
+    /// buffers are resampled as one continuous signal.
+    pub struct Resampler {
+        inner: Samplerate,
+    }
+
+    // The libsamplerate state isn't tied to the thread that created it
+    unsafe impl Send for Resampler {}
+
+    impl Resampler {
+        pub fn new(from_rate: u32, to_rate: u32, quality: ResampleQuality) -> anyhow::Result<Self> {
+            let inner = Samplerate::new(quality.converter_type(), from_rate, to_rate, 1)
+                .map_err(|e| anyhow::Error::msg(format!("Failed to create resampler: {e}")))?;
+            Ok(Self { inner })
+        }
+
+        pub fn process(&mut self, data: &[f32]) -> Vec<f32> {
+            self.inner.process(data).unwrap_or_else(|e| {
+                log::warn!("resampling failed: {}", e);
+                Vec::new()
+            })
+        }
+    }
+
+    pub struct Converter {
+        format: StreamFormat,
+        resampler: Option<Resampler>,
+    }
+
+    impl Converter {
+        pub fn new(format: StreamFormat, quality: ResampleQuality) -> anyhow::Result<Self> {
+            let resampler = (format.sample_rate != TARGET_SAMPLE_RATE)
+                .then(|| Resampler::new(format.sample_rate, TARGET_SAMPLE_RATE, quality))
+                .transpose()?;
+            Ok(Self { format, resampler })
+        }
+
+        /// Converts one callback buffer to 16kHz mono.
+        pub fn process<S: Sample>(&mut self, data: &[S]) -> Vec<f32> {
+            let mono = downmix(data, self.format.channels);
+            match self.resampler.as_mut() {
+                Some(resampler) => resampler.process(&mono),
+                None => mono,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn integer_samples_are_normalized() {
+            assert_eq!(0i16.to_f32(), 0.0);
+            assert_eq!(i16::MIN.to_f32(), -1.0);
+            assert!((i16::MAX.to_f32() - 1.0).abs() < 1e-4);
+            assert_eq!(32768u16.to_f32(), 0.0);
+            assert_eq!(0u16.to_f32(), -1.0);
+            assert!((u16::MAX.to_f32() - 1.0).abs() < 1e-4);
+        }
+
+        #[test]
+        fn downmix_averages_every_channel() {
+            let stereo = [0.2f32, 0.4, -1.0, 1.0];
+            assert_eq!(downmix(&stereo, 2), vec![0.3f32, 0.0]);
+
+            // 5.1: only the first channel of the first frame is set
+            let mut surround = vec![0.0f32; 12];
+            surround[0] = 0.6;
+            surround[6..].fill(0.5);
+            let mono = downmix(&surround, 6);
+            assert_eq!(mono.len(), 2);
+            assert!((mono[0] - 0.1).abs() < 1e-6);
+            assert!((mono[1] - 0.5).abs() < 1e-6);
+        }
+
+        #[test]
+        fn downmix_drops_partial_frames() {
+            assert_eq!(downmix(&[1i16, 1, 1], 2).len(), 1);
+            assert_eq!(downmix(&[0.5f32, 0.25], 1), vec![0.5f32, 0.25]);
+        }
+
+        fn converter(sample_rate: u32, channels: u16) -> Converter {
+            let format = StreamFormat {
+                sample_rate,
+                channels,
+            };
+            Converter::new(format, ResampleQuality::default()).unwrap()
+        }
+
+        #[test]
+        fn converter_keeps_16k_mono_untouched() {
+            let mut converter = converter(16000, 1);
+            let data: Vec<f32> = (0..320).map(|i| i as f32 / 320.0).collect();
+            assert_eq!(converter.process(&data), data);
+        }
+
+        #[test]
+        fn converter_resamples_to_16k() {
+            for (sample_rate, channels) in [(44100, 2), (48000, 6), (8000, 1)] {
+                let mut converter = converter(sample_rate, channels);
+                // One second of a 440Hz tone on every channel, in 10ms callbacks
+                let data: Vec<i16> = (0..sample_rate as usize)
+                    .flat_map(|i| {
+                        let t = i as f32 / sample_rate as f32;
+                        let s = ((t * 440.0 * std::f32::consts::TAU).sin() * 16000.0) as i16;
+                        std::iter::repeat_n(s, channels as usize)
+                    })
+                    .collect();
+                let chunk = sample_rate as usize / 100 * channels as usize;
+                let out: Vec<f32> = data
+                    .chunks(chunk)
+                    .flat_map(|chunk| converter.process(chunk))
+                    .collect();
+                let expected = TARGET_SAMPLE_RATE as usize;
+                assert!(
+                    // The filter holds back a few samples of latency
+                    out.len().abs_diff(expected) < expected / 50,
+                    "{sample_rate}Hz x{channels}: got {} samples",
+                    out.len()
+                );
+                let peak = out.iter().fold(0f32, |m, s| m.max(s.abs()));
+                assert!(peak > 0.4 && peak < 0.6, "peak {peak}");
+            }
+        }
+    }
 }
 
 #[cfg(target_os = "macos")]
@@ -156,10 +1394,68 @@ mod macos {
     };
     use futures::executor::block_on;
 
-    use super::audio_resample;
+    use super::format::{Converter, StreamFormat};
+    use super::{AudioDevice, AudioSource, CaptureSettings, CaptureTarget, DeviceKind};
+
+    fn display_id(display: &sc::Display) -> String {
+        format!("display:{}", display.display_id().0)
+    }
+
+    fn app_id(app: &sc::RunningApp) -> String {
+        format!("app:{}", app.bundle_id())
+    }
+
+    /// ScreenCaptureKit captures system audio per display, so displays are
+    /// all there is to pick. Its microphone stream always records the default
+    /// input, which is why no inputs are listed either.
+    pub fn list_devices() -> anyhow::Result<Vec<AudioDevice>> {
+        let content = block_on(sc::ShareableContent::current())?;
+        let devices = content
+            .displays()
+            .iter()
+            .enumerate()
+            .map(|(i, display)| AudioDevice {
+                id: display_id(display),
+                name: format!(
+                    "Display {} ({}x{})",
+                    i + 1,
+                    display.width(),
+                    display.height()
+                ),
+                kind: DeviceKind::Output,
+                is_default: i == 0,
+            })
+            .collect();
+        Ok(devices)
+    }
+
+    /// Running applications with a bundle id, which stays the same across launches.
+    pub fn list_targets() -> anyhow::Result<Vec<CaptureTarget>> {
+        let content = block_on(sc::ShareableContent::current())?;
+        let own_pid = std::process::id() as i32;
+        let mut targets: Vec<CaptureTarget> = content
+            .apps()
+            .iter()
+            .filter(|app| app.process_id() != own_pid && !app.bundle_id().is_empty())
+            .map(|app| CaptureTarget {
+                id: app_id(app),
+                name: app.app_name().to_string(),
+                pid: u32::try_from(app.process_id()).ok(),
+            })
+            .collect();
+        targets.sort_by_key(|target| target.name.to_lowercase());
+        Ok(targets)
+    }
+
+    // ScreenCaptureKit delivers 48kHz non-interleaved f32, only the first plane is used
+    const FORMAT: StreamFormat = StreamFormat {
+        sample_rate: 48000,
+        channels: 1,
+    };
 
     struct StreamOutputInner {
         on_data: Box<dyn Fn(Vec<f32>) + Send>,
+        converter: Converter,
     }
 
     impl StreamOutputInner {
@@ -173,7 +1469,7 @@ mod macos {
                     buffer.data_bytes_size as usize / std::mem::size_of::<f32>(),
                 )
             };
-            let resampled: Vec<f32> = audio_resample(samples, 48000, 16000, 1);
+            let resampled = self.converter.process(samples);
             (self.on_data)(resampled);
         }
     }
@@ -193,7 +1489,7 @@ mod macos {
             match kind {
                 sc::OutputType::Screen => {}
                 sc::OutputType::Audio => self.inner_mut().handle_audio(sample_buf),
-                sc::OutputType::Mic => {}
+                sc::OutputType::Mic => self.inner_mut().handle_audio(sample_buf),
             }
         }
     }
@@ -207,40 +1503,382 @@ mod macos {
     unsafe impl Sync for MacAudioOutput {}
 
     impl MacAudioOutput {
-        pub fn new(on_data: Box<dyn Fn(Vec<f32>) + Send>) -> Self {
-            let inner = StreamOutputInner { on_data };
+        pub fn new(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            settings: &CaptureSettings,
+        ) -> anyhow::Result<Self> {
+            Self::open(on_data, settings, sc::OutputType::Audio)
+        }
+
+        /// Captures the default microphone through a stream of its own, so it
+        /// arrives as a separate track. `mic_device` is ignored, the input is
+        /// chosen in the system settings.
+        pub fn new_mic(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            settings: &CaptureSettings,
+        ) -> anyhow::Result<Self> {
+            Self::open(on_data, settings, sc::OutputType::Mic)
+        }
+
+        fn open(
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+            settings: &CaptureSettings,
+            kind: sc::OutputType,
+        ) -> anyhow::Result<Self> {
+            let mic = matches!(kind, sc::OutputType::Mic);
+            let inner = StreamOutputInner {
+                on_data,
+                converter: Converter::new(FORMAT, settings.quality)?,
+            };
             let delegate = StreamOutput::with(inner);
-            let content = block_on(sc::ShareableContent::current()).unwrap();
+            let content = block_on(sc::ShareableContent::current())?;
             let displays = content.displays().clone();
-            let display = displays.first().expect("No display found");
-            let filter = sc::ContentFilter::with_display_excluding_windows(
-                display,
-                &cidre::ns::Array::new(),
-            );
+            let display = match settings.device.as_deref() {
+                Some(id) => displays.iter().find(|d| display_id(d) == id),
+                None => displays.first(),
+            }
+            .ok_or_else(|| anyhow::Error::msg("No display found"))?;
+            // The microphone isn't tied to an application
+            let filter = match settings.target.as_deref().filter(|_| !mic) {
+                Some(target) => {
+                    let apps = content.apps();
+                    let app = apps
+                        .iter()
+                        .find(|app| app_id(app) == target)
+                        .ok_or_else(|| {
+                            anyhow::Error::msg(format!("Application '{target}' is not running"))
+                        })?;
+                    sc::ContentFilter::with_display_including_apps_excepting_windows(
+                        display,
+                        &cidre::ns::Array::from_slice(&[app]),
+                        &cidre::ns::Array::new(),
+                    )
+                }
+                None => sc::ContentFilter::with_display_excluding_windows(
+                    display,
+                    &cidre::ns::Array::new(),
+                ),
+            };
 
             let queue = dispatch::Queue::serial_with_ar_pool();
             let mut cfg = sc::StreamCfg::new();
-            cfg.set_captures_audio(true);
+            cfg.set_captures_audio(!mic);
+            cfg.set_capture_mic(mic);
             cfg.set_excludes_current_process_audio(false);
 
             let stream = sc::Stream::new(&filter, &cfg);
-            stream
-                .add_stream_output(delegate.as_ref(), sc::OutputType::Audio, Some(&queue))
-                .expect("Failed to add stream output");
-            Self {
+            stream.add_stream_output(delegate.as_ref(), kind, Some(&queue))?;
+            Ok(Self {
                 _output: delegate,
                 stream,
-            }
+            })
         }
+    }
 
-        pub fn start_recording(&self) -> anyhow::Result<()> {
+    impl AudioSource for MacAudioOutput {
+        fn start_recording(&self) -> anyhow::Result<()> {
             block_on(self.stream.start())?;
             log::info!("stream started");
             Ok(())
         }
 
-        pub fn stop_recording(&self) {
-            block_on(self.stream.stop()).unwrap();
+        fn stop_recording(&self) {
+            if let Err(e) = block_on(self.stream.stop()) {
+                log::warn!("failed to stop stream: {}", e);
+            }
+        }
+    }
+}
+
+mod file {
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    use symphonia::core::{
+        audio::SampleBuffer,
+        codecs::{DecoderOptions, CODEC_TYPE_NULL},
+        errors::Error,
+        formats::FormatOptions,
+        io::MediaSourceStream,
+        meta::MetadataOptions,
+        probe::Hint,
+    };
+
+    use super::format::downmix;
+    use super::{audio_resample, AudioSource};
+
+    const SAMPLE_RATE: u32 = 16000;
+    // 20ms per chunk, one VAD frame, so real-time playback advances in the
+    // steps the detector works in
+    const FRAME_SAMPLES: usize = 320;
+
+    pub struct FileAudioSource {
+        samples: Arc<Vec<f32>>,
+        realtime: bool,
+        on_data: Arc<Mutex<Box<dyn Fn(Vec<f32>) + Send>>>,
+        // Playback position in samples, kept so pausing resumes where it stopped
+        position: Arc<Mutex<usize>>,
+        running: Arc<AtomicBool>,
+        handle: Mutex<Option<JoinHandle<()>>>,
+    }
+
+    impl FileAudioSource {
+        pub fn open(
+            path: &Path,
+            realtime: bool,
+            on_data: Box<dyn Fn(Vec<f32>) + Send>,
+        ) -> anyhow::Result<Self> {
+            let samples = decode(path)?;
+            log::info!(
+                "decoded {}: {:.1}s of audio",
+                path.display(),
+                samples.len() as f32 / SAMPLE_RATE as f32
+            );
+            Ok(Self {
+                samples: Arc::new(samples),
+                realtime,
+                on_data: Arc::new(Mutex::new(on_data)),
+                position: Arc::new(Mutex::new(0)),
+                running: Arc::new(AtomicBool::new(false)),
+                handle: Mutex::new(None),
+            })
+        }
+    }
+
+    impl AudioSource for FileAudioSource {
+        fn start_recording(&self) -> anyhow::Result<()> {
+            let mut handle = self.handle.lock().unwrap();
+            if handle.as_ref().is_some_and(|h| !h.is_finished()) {
+                return Ok(());
+            }
+            let samples = self.samples.clone();
+            let realtime = self.realtime;
+            let on_data = self.on_data.clone();
+            let position = self.position.clone();
+            let running = self.running.clone();
+            running.store(true, Ordering::SeqCst);
+            handle.replace(std::thread::spawn(move || {
+                let frame = Duration::from_secs_f64(FRAME_SAMPLES as f64 / SAMPLE_RATE as f64);
+                let started = Instant::now();
+                let mut played = 0u32;
+                while running.load(Ordering::SeqCst) {
+                    let chunk = {
+                        let mut position = position.lock().unwrap();
+                        if *position >= samples.len() {
+                            log::info!("file playback finished");
+                            break;
+                        }
+                        let end = (*position + FRAME_SAMPLES).min(samples.len());
+                        let chunk = samples[*position..end].to_vec();
+                        *position = end;
+                        chunk
+                    };
+                    (on_data.lock().unwrap())(chunk);
+                    if realtime {
+                        played += 1;
+                        if let Some(wait) = (frame * played).checked_sub(started.elapsed()) {
+                            std::thread::sleep(wait);
+                        }
+                    }
+                }
+                running.store(false, Ordering::SeqCst);
+            }));
+            Ok(())
+        }
+
+        fn stop_recording(&self) {
+            self.running.store(false, Ordering::SeqCst);
+            if let Some(handle) = self.handle.lock().unwrap().take() {
+                let _ = handle.join();
+            }
+        }
+    }
+
+    pub fn decode(path: &Path) -> anyhow::Result<Vec<f32>> {
+        let file = std::fs::File::open(path)?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let mut format = probed.format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow::Error::msg("No audio track found"))?;
+        let track_id = track.id;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| anyhow::Error::msg("Unknown sample rate"))?;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        let mut mono = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(e)) => {
+                    log::warn!("skip corrupted packet: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buf.copy_interleaved_ref(decoded);
+            mono.extend(downmix(buf.samples(), channels as u16));
+        }
+
+        if sample_rate == SAMPLE_RATE {
+            Ok(mono)
+        } else {
+            Ok(audio_resample(&mono, sample_rate, SAMPLE_RATE, 1))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES_PER_SECOND: usize = 16000 / FRAME_SAMPLES;
+
+    /// A 440Hz tone with the given amplitude for each `(amplitude, frames)`,
+    /// amplitude 0 being silence.
+    fn signal(parts: &[(f32, usize)]) -> Vec<f32> {
+        parts
+            .iter()
+            .flat_map(|&(amplitude, frames)| std::iter::repeat_n(amplitude, frames * FRAME_SAMPLES))
+            .enumerate()
+            .map(|(i, amplitude)| {
+                amplitude * (i as f32 / 16000.0 * 440.0 * std::f32::consts::TAU).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn utterances_include_the_pre_roll_and_the_hangover() {
+        let samples = signal(&[
+            (0.0, FRAMES_PER_SECOND),
+            (0.3, FRAMES_PER_SECOND),
+            (0.0, 2 * FRAMES_PER_SECOND),
+        ]);
+        let windows = split_utterances(&samples, Track::Them);
+        assert_eq!(windows.len(), 1);
+        let window = &windows[0];
+        assert!(window.is_final);
+        // Speech is detected on the third loud frame, the pre-roll reaches back
+        // from the frame before it
+        assert_eq!(window.start, 16000 + 2 * FRAME_SAMPLES - PRE_ROLL);
+        // The detector holds on for end_frames - 1 frames of silence
+        assert_eq!(window.end, 2 * 16000 + 24 * FRAME_SAMPLES);
+        assert_eq!(window.samples, samples[window.start..window.end]);
+    }
+
+    #[test]
+    fn quiet_audio_is_not_an_utterance() {
+        // About -63dB, below the detector's minimum level
+        let samples = signal(&[(0.0, 10), (0.001, 3 * FRAMES_PER_SECOND)]);
+        assert!(split_utterances(&samples, Track::Them).is_empty());
+    }
+
+    #[test]
+    fn previews_come_every_update_interval() {
+        let samples = signal(&[(0.0, 10), (0.3, 3 * FRAMES_PER_SECOND), (0.0, 50)]);
+        let mut chunker = Chunker::new(Track::Me);
+        let windows = chunker.push(samples);
+        let (last, previews) = windows.split_last().unwrap();
+        assert!(last.is_final);
+        assert!(!previews.is_empty());
+        for pair in previews.windows(2) {
+            assert_eq!(
+                pair[1].samples.len() - pair[0].samples.len(),
+                UPDATE_INTERVAL
+            );
+        }
+        for preview in previews {
+            assert!(!preview.is_final);
+            assert_eq!(preview.start, last.start);
+            assert_eq!(preview.track, Track::Me);
+            assert!(preview.samples.len() >= MIN_WINDOW);
+        }
+    }
+
+    #[test]
+    fn long_speech_is_cut_at_max_utterance() {
+        let samples = signal(&[(0.3, 20 * FRAMES_PER_SECOND)]);
+        let windows = split_utterances(&samples, Track::Them);
+        assert_eq!(windows.len(), 2);
+        assert!(windows.iter().all(|window| window.is_final));
+        assert_eq!(windows[0].start, 0);
+        assert_eq!(windows[0].samples.len(), MAX_UTTERANCE);
+        assert_eq!(windows[0].end, MAX_UTTERANCE);
+        // The rest continues without a gap and ends with the stream
+        assert_eq!(windows[1].start, windows[0].end);
+        assert_eq!(windows[1].end, samples.len());
+    }
+
+    #[test]
+    fn short_utterances_are_dropped_or_padded() {
+        // A click cut off by the end of the stream
+        let mut chunker = Chunker::new(Track::Them);
+        assert!(chunker.push(signal(&[(0.0, 10), (0.3, 3)])).is_empty());
+        assert!(chunker.finish().is_none());
+
+        let mut chunker = Chunker::new(Track::Them);
+        assert!(chunker.push(signal(&[(0.0, 10), (0.3, 25)])).is_empty());
+        let window = chunker.finish().unwrap();
+        assert!(window.end - window.start >= MIN_UTTERANCE);
+        assert!(window.end - window.start < MIN_WINDOW);
+        assert_eq!(window.samples.len(), MIN_WINDOW);
+        assert!(window.samples[window.end - window.start..]
+            .iter()
+            .all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn splitting_does_not_depend_on_the_chunk_size() {
+        let samples = signal(&[
+            (0.0, 20),
+            (0.3, 2 * FRAMES_PER_SECOND),
+            (0.0, FRAMES_PER_SECOND),
+            (0.2, FRAMES_PER_SECOND),
+            (0.0, FRAMES_PER_SECOND),
+        ]);
+        let whole = split_utterances(&samples, Track::Them);
+        assert_eq!(whole.len(), 2);
+
+        let mut splitter = UtteranceSplitter::new(Track::Them);
+        let mut streamed: Vec<AudioWindow> = samples
+            .chunks(777)
+            .flat_map(|chunk| splitter.push(chunk))
+            .collect();
+        streamed.extend(splitter.finish());
+        assert_eq!(streamed.len(), whole.len());
+        for (a, b) in streamed.iter().zip(&whole) {
+            assert!(a.is_final);
+            assert_eq!((a.start, a.end), (b.start, b.end));
+            assert_eq!(a.samples, b.samples);
         }
     }
 }
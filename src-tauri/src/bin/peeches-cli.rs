@@ -0,0 +1,213 @@
+//! Headless transcription and translation of audio files or raw PCM from stdin.
+//!
+//! ```sh
+//! peeches-cli --whisper-model ggml-base-q5_1.bin \
+//!     --translate-model opus-mt-en-zh.bin \
+//!     --source-tokenizer model/tokenizer-marian-base-en.json \
+//!     --target-tokenizer model/tokenizer-marian-base-zh.json \
+//!     meeting.flac
+//!
+//! ffmpeg -i talk.mp4 -f s16le -ar 16000 -ac 1 - | peeches-cli --whisper-model ggml-base-q5_1.bin -
+//! ```
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use peeches_lib::{
+    audio::{
+        self,
+        format::{Converter, ResampleQuality, StreamFormat, TARGET_SAMPLE_RATE},
+        AudioWindow, Track, UtteranceSplitter,
+    },
+    device::DevicePreference,
+    registry::Registry,
+    translate::{ModelConfig, Translator},
+    whisper::{Whisper, AUTO_LANGUAGE},
+};
+
+const SAMPLE_RATE: usize = 16000;
+
+/// Samples read from stdin at a time
+const READ_SAMPLES: usize = SAMPLE_RATE / 2;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PcmFormat {
+    S16le,
+    F32le,
+}
+
+#[derive(Parser)]
+#[command(version, about = "Transcribe and translate audio without the GUI")]
+struct Args {
+    /// Audio file (WAV/FLAC/MP3), or `-` to read 16kHz mono raw PCM from stdin
+    input: String,
+
+    /// Path to the ggml whisper model
+    #[arg(long)]
+    whisper_model: PathBuf,
+
+    /// Path to the Marian translation model (safetensors); omit to only transcribe
+    #[arg(long, requires_all = ["source_tokenizer", "target_tokenizer"])]
+    translate_model: Option<PathBuf>,
+
+    /// Built-in pair whose config is used when the model has no config.json
+    #[arg(long, default_value = peeches_lib::registry::DEFAULT_PAIR)]
+    pair: String,
+
+    /// Tokenizer of the source language
+    #[arg(long)]
+    source_tokenizer: Option<PathBuf>,
+
+    /// Tokenizer of the target language
+    #[arg(long)]
+    target_tokenizer: Option<PathBuf>,
+
+    /// Sample format of the PCM stream read from stdin
+    #[arg(long, value_enum, default_value = "s16le")]
+    pcm_format: PcmFormat,
+
+    /// Spoken language as an ISO 639-1 code, or `auto` to detect it
+    #[arg(long, default_value = "en")]
+    language: String,
+
+    /// Device to run the models on: auto, gpu or cpu
+    #[arg(long, default_value = "auto")]
+    device: DevicePreference,
+}
+
+struct Pipeline {
+    whisper: Whisper,
+    translator: Option<Translator>,
+}
+
+impl Pipeline {
+    /// Transcribes and translates one utterance.
+    fn process(&mut self, window: AudioWindow) -> anyhow::Result<()> {
+        let start = window.start;
+        let transcript = self.whisper.transcribe(window.samples)?;
+        for segment in &transcript.segments {
+            let text = segment.text.trim();
+            if text.is_empty() || text == "[BLANK_AUDIO]" || segment.is_likely_silence() {
+                continue;
+            }
+            let at = timestamp(start + segment.start_ms as usize * SAMPLE_RATE / 1000);
+            if self.whisper.language() == AUTO_LANGUAGE {
+                println!("[{}] ({}) {}", at, transcript.language, text);
+            } else {
+                println!("[{}] {}", at, text);
+            }
+            if let Some(translator) = self.translator.as_mut() {
+                println!("[{}] {}", at, translator.translate(text)?);
+            }
+            println!();
+        }
+        Ok(())
+    }
+}
+
+fn timestamp(samples: usize) -> String {
+    let ms = samples * 1000 / SAMPLE_RATE;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Reads up to `len` samples, decoded the same way as captured audio.
+fn read_stdin_chunk(
+    stdin: &mut impl Read,
+    format: PcmFormat,
+    converter: &mut Converter,
+    len: usize,
+) -> anyhow::Result<Vec<f32>> {
+    let width = match format {
+        PcmFormat::S16le => 2,
+        PcmFormat::F32le => 4,
+    };
+    let mut bytes = Vec::with_capacity(len * width);
+    stdin.take((len * width) as u64).read_to_end(&mut bytes)?;
+    let samples = match format {
+        PcmFormat::S16le => converter.process(
+            &bytes
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        PcmFormat::F32le => converter.process(
+            &bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect::<Vec<_>>(),
+        ),
+    };
+    Ok(samples)
+}
+
+/// The loaders take paths as strings.
+fn utf8(path: &Path) -> anyhow::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow::Error::msg(format!("{} is not valid UTF-8", path.display())))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let mut whisper = Whisper::new(utf8(&args.whisper_model)?, args.device)?;
+    whisper.set_language(&args.language)?;
+    eprintln!("whisper running on {:?}", whisper.device());
+    let translator = match &args.translate_model {
+        Some(model) => Some(Translator::new(
+            utf8(model)?,
+            ModelConfig::load(
+                model,
+                Registry::default()
+                    .get(&args.pair)
+                    .and_then(|pair| pair.config.as_ref()),
+            )?,
+            utf8(args.source_tokenizer.as_ref().unwrap())?,
+            utf8(args.target_tokenizer.as_ref().unwrap())?,
+            args.device,
+        )?),
+        None => None,
+    };
+    let mut pipeline = Pipeline {
+        whisper,
+        translator,
+    };
+
+    // Cut at pauses like live capture, so words aren't split between windows
+    if args.input == "-" {
+        let mut stdin = std::io::stdin().lock();
+        let mut converter = Converter::new(
+            StreamFormat {
+                sample_rate: TARGET_SAMPLE_RATE,
+                channels: 1,
+            },
+            ResampleQuality::default(),
+        )?;
+        let mut splitter = UtteranceSplitter::new(Track::Them);
+        loop {
+            let samples =
+                read_stdin_chunk(&mut stdin, args.pcm_format, &mut converter, READ_SAMPLES)?;
+            if samples.is_empty() {
+                break;
+            }
+            for window in splitter.push(&samples) {
+                pipeline.process(window)?;
+            }
+        }
+        if let Some(window) = splitter.finish() {
+            pipeline.process(window)?;
+        }
+    } else {
+        let samples = audio::decode_file(&PathBuf::from(&args.input))?;
+        for window in audio::split_utterances(&samples, Track::Them) {
+            pipeline.process(window)?;
+        }
+    }
+    Ok(())
+}
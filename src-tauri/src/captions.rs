@@ -0,0 +1,406 @@
+//! Serves captions to other programs on this machine, such as OBS browser
+//! sources, dashboards or note-taking bots.
+//!
+//! Every `partial` and `final` event goes out as JSON over a WebSocket at
+//! `/ws`, with a `type` field naming the event. `/` serves a page showing the
+//! captions, ready to be used as a browser source. Both need the token, given
+//! as `?token=` or as an `Authorization: Bearer` header.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+pub const DEFAULT_PORT: u16 = 7789;
+
+/// Messages kept for a client that is slow to read, older ones are dropped
+const BACKLOG: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptionServerSettings {
+    pub port: u16,
+    pub token: String,
+}
+
+impl CaptionServerSettings {
+    /// Settings with a new random token.
+    pub fn new(port: u16) -> Self {
+        let token: [u8; 16] = rand::thread_rng().gen();
+        Self {
+            port,
+            token: token.iter().map(|b| format!("{b:02x}")).collect(),
+        }
+    }
+}
+
+struct Running {
+    settings: CaptionServerSettings,
+    /// Dropping it ends the connections of the clients
+    sender: broadcast::Sender<Arc<str>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Clone, Default)]
+pub struct CaptionServer {
+    running: Arc<Mutex<Option<Running>>>,
+}
+
+impl CaptionServer {
+    /// The settings of the server if it's running.
+    pub fn settings(&self) -> Option<CaptionServerSettings> {
+        let running = self.running.lock().unwrap();
+        running.as_ref().map(|running| running.settings.clone())
+    }
+
+    /// Starts listening on localhost, replacing the server running before.
+    pub async fn start(&self, settings: CaptionServerSettings) -> anyhow::Result<()> {
+        let previous = self.running.lock().unwrap().take();
+        if let Some(previous) = previous {
+            previous.task.abort();
+            // The listener has to be closed before its port can be bound again
+            let _ = previous.task.await;
+        }
+        let listener = TcpListener::bind(("127.0.0.1", settings.port))
+            .await
+            .map_err(|e| {
+                anyhow::Error::msg(format!("Port {} is unavailable: {}", settings.port, e))
+            })?;
+        log::info!("caption server listening on 127.0.0.1:{}", settings.port);
+        let (sender, _) = broadcast::channel(BACKLOG);
+        let task = tokio::spawn(serve(listener, settings.token.clone(), sender.clone()));
+        *self.running.lock().unwrap() = Some(Running {
+            settings,
+            sender,
+            task,
+        });
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            log::info!("caption server stopped");
+            running.task.abort();
+        }
+    }
+
+    /// Sends an event to every connected client.
+    pub fn publish<T: Serialize>(&self, kind: &str, payload: &T) {
+        let running = self.running.lock().unwrap();
+        let Some(running) = running.as_ref() else {
+            return;
+        };
+        if running.sender.receiver_count() == 0 {
+            return;
+        }
+        let mut message = match serde_json::to_value(payload) {
+            Ok(serde_json::Value::Object(message)) => message,
+            Ok(_) => return,
+            Err(e) => {
+                log::error!("failed to serialize caption: {}", e);
+                return;
+            }
+        };
+        message.insert("type".to_string(), kind.into());
+        let _ = running
+            .sender
+            .send(serde_json::Value::Object(message).to_string().into());
+    }
+}
+
+async fn serve(listener: TcpListener, token: String, sender: broadcast::Sender<Arc<str>>) {
+    let token: Arc<str> = token.into();
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                log::error!("caption server failed to accept: {}", e);
+                continue;
+            }
+        };
+        let token = token.clone();
+        let captions = sender.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &token, captions).await {
+                log::debug!("caption client {} disconnected: {}", addr, e);
+            }
+        });
+    }
+}
+
+struct Request {
+    path: String,
+    token: Option<String>,
+    upgrade: bool,
+}
+
+impl Request {
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.lines();
+        let target = lines.next()?.split_whitespace().nth(1)?;
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut token = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .map(str::to_string);
+        let mut upgrade = false;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("authorization") {
+                if let Some(bearer) = value.strip_prefix("Bearer ") {
+                    token = Some(bearer.trim().to_string());
+                }
+            } else if name.eq_ignore_ascii_case("upgrade") {
+                upgrade = value.eq_ignore_ascii_case("websocket");
+            }
+        }
+        Some(Self {
+            path: path.to_string(),
+            token,
+            upgrade,
+        })
+    }
+}
+
+async fn handle(
+    stream: TcpStream,
+    token: &str,
+    mut captions: broadcast::Receiver<Arc<str>>,
+) -> anyhow::Result<()> {
+    let head = peek_head(&stream).await?;
+    let request = Request::parse(&head).ok_or_else(|| anyhow::Error::msg("malformed request"))?;
+    if !token_matches(request.token.as_deref(), token) {
+        return respond(
+            stream,
+            head.len(),
+            "401 Unauthorized",
+            "text/plain",
+            "Invalid token",
+        )
+        .await;
+    }
+    match (request.path.as_str(), request.upgrade) {
+        ("/ws", true) => {}
+        ("/", false) => {
+            return respond(
+                stream,
+                head.len(),
+                "200 OK",
+                "text/html; charset=utf-8",
+                PAGE,
+            )
+            .await;
+        }
+        _ => {
+            return respond(
+                stream,
+                head.len(),
+                "404 Not Found",
+                "text/plain",
+                "Not found",
+            )
+            .await
+        }
+    }
+
+    // The handshake reads the request itself
+    let socket = tokio_tungstenite::accept_async(stream).await?;
+    let (mut sink, mut messages) = socket.split();
+    loop {
+        tokio::select! {
+            caption = captions.recv() => match caption {
+                Ok(caption) => sink.send(Message::Text(caption.to_string())).await?,
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("caption client fell behind, skipped {} messages", skipped);
+                }
+                // The server was stopped
+                Err(RecvError::Closed) => {
+                    sink.send(Message::Close(None)).await?;
+                    return Ok(());
+                }
+            },
+            message = messages.next() => match message {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Err(e)) => return Err(e.into()),
+                // Pings are answered by tungstenite
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Compares without stopping at the first difference, so response times don't
+/// give away how much of a guess was right.
+fn token_matches(given: Option<&str>, token: &str) -> bool {
+    let Some(given) = given else {
+        return false;
+    };
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+    let diff = given
+        .iter()
+        .zip(token)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    // The length is no secret, every token is 32 hex digits
+    std::hint::black_box(diff) == 0 && given.len() == token.len()
+}
+
+/// Waits for the whole request head without consuming it.
+async fn peek_head(stream: &TcpStream) -> anyhow::Result<String> {
+    let mut buf = [0u8; 4096];
+    for _ in 0..100 {
+        let len = stream.peek(&mut buf).await?;
+        if len == 0 {
+            anyhow::bail!("connection closed");
+        }
+        if let Some(end) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok(String::from_utf8_lossy(&buf[..end + 4]).into_owned());
+        }
+        if len == buf.len() {
+            anyhow::bail!("request head too large");
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    anyhow::bail!("incomplete request")
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    head_len: usize,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> anyhow::Result<()> {
+    // Consume the request, closing with unread data would reset the connection
+    let mut head = vec![0u8; head_len];
+    stream.read_exact(&mut head).await?;
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Shows the latest caption on a transparent background.
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Peeches</title>
+<style>
+  body { margin: 0; background: transparent; font-family: sans-serif; color: #fff;
+         text-shadow: 0 0 4px #000, 0 0 2px #000; }
+  #captions { position: fixed; left: 0; right: 0; bottom: 0; padding: 16px; text-align: center; }
+  #original { font-size: 28px; }
+  #tentative { opacity: 0.6; }
+  #translated { font-size: 32px; margin-top: 4px; }
+</style>
+</head>
+<body>
+<div id="captions">
+  <div id="original"><span id="committed"></span><span id="tentative"></span></div>
+  <div id="translated"></div>
+</div>
+<script>
+  const token = new URLSearchParams(location.search).get("token");
+  function connect() {
+    const socket = new WebSocket(`ws://${location.host}/ws?token=${encodeURIComponent(token)}`);
+    socket.onmessage = (message) => {
+      const caption = JSON.parse(message.data);
+      const final = caption.type === "final";
+      document.getElementById("committed").textContent =
+        final ? caption.originalText : caption.committedText;
+      document.getElementById("tentative").textContent = final ? "" : caption.tentativeText;
+      // Sentences are translated once complete
+      if (final) document.getElementById("translated").textContent = caption.translatedText;
+    };
+    socket.onclose = () => setTimeout(connect, 1000);
+  }
+  connect();
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn reads_the_token_from_the_query() {
+        let request = Request::parse(&format!(
+            "GET /ws?lang=en&token={TOKEN} HTTP/1.1\r\nHost: localhost\r\n\
+             Upgrade: WebSocket\r\nConnection: Upgrade\r\n\r\n"
+        ))
+        .unwrap();
+        assert_eq!(request.path, "/ws");
+        assert_eq!(request.token.as_deref(), Some(TOKEN));
+        assert!(request.upgrade);
+
+        let request = Request::parse("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/");
+        assert_eq!(request.token, None);
+        assert!(!request.upgrade);
+    }
+
+    #[test]
+    fn reads_the_token_from_a_bearer_header() {
+        let request = Request::parse(&format!(
+            "GET /ws?token=other HTTP/1.1\r\nauthorization:  Bearer {TOKEN} \r\n\r\n"
+        ))
+        .unwrap();
+        assert_eq!(request.token.as_deref(), Some(TOKEN));
+
+        // Other schemes are no token
+        let request = Request::parse(&format!(
+            "GET / HTTP/1.1\r\nAuthorization: Basic {TOKEN}\r\n\r\n"
+        ))
+        .unwrap();
+        assert_eq!(request.token, None);
+    }
+
+    #[test]
+    fn rejects_a_malformed_request_line() {
+        assert!(Request::parse("").is_none());
+        assert!(Request::parse("GET\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        assert!(token_matches(Some(TOKEN), TOKEN));
+        assert!(!token_matches(None, TOKEN));
+        assert!(!token_matches(Some(""), TOKEN));
+        assert!(!token_matches(
+            Some("0123456789abcdef0123456789abcdee"),
+            TOKEN
+        ));
+        assert!(!token_matches(Some(&TOKEN[..31]), TOKEN));
+        assert!(!token_matches(Some(&format!("{TOKEN}0")), TOKEN));
+
+        let header = Request::parse(&format!(
+            "GET /ws HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+            &TOKEN[..16]
+        ))
+        .unwrap();
+        assert!(!token_matches(header.token.as_deref(), TOKEN));
+        let header = Request::parse(&format!(
+            "GET /ws HTTP/1.1\r\nAuthorization: Bearer {TOKEN}\r\n\r\n"
+        ))
+        .unwrap();
+        assert!(token_matches(header.token.as_deref(), TOKEN));
+    }
+}
@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Which device the user wants the models to run on.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DevicePreference {
+    /// Use the GPU when one is usable, otherwise fall back to the CPU
+    #[default]
+    Auto,
+    Gpu,
+    Cpu,
+}
+
+impl FromStr for DevicePreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "gpu" => Ok(Self::Gpu),
+            "cpu" => Ok(Self::Cpu),
+            _ => Err(format!("unknown device '{s}', expected auto, gpu or cpu")),
+        }
+    }
+}
+
+/// The device a model actually ended up on.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ComputeDevice {
+    Metal,
+    Cuda,
+    Cpu,
+}
+
+impl ComputeDevice {
+    /// The GPU backend this build was compiled with, if any.
+    pub fn gpu() -> Option<Self> {
+        if cfg!(target_os = "macos") {
+            Some(Self::Metal)
+        } else if cfg!(target_os = "windows") {
+            Some(Self::Cuda)
+        } else {
+            None
+        }
+    }
+}
+
+/// Picks a candle device for `preference`, falling back to the CPU in `Auto` mode.
+pub fn candle_device(
+    preference: DevicePreference,
+) -> anyhow::Result<(candle_core::Device, ComputeDevice)> {
+    if preference == DevicePreference::Cpu {
+        return Ok((candle_core::Device::Cpu, ComputeDevice::Cpu));
+    }
+    let gpu = match ComputeDevice::gpu() {
+        Some(ComputeDevice::Metal) => {
+            candle_core::Device::new_metal(0).map(|d| (d, ComputeDevice::Metal))
+        }
+        Some(ComputeDevice::Cuda) => {
+            candle_core::Device::new_cuda(0).map(|d| (d, ComputeDevice::Cuda))
+        }
+        _ => Err(candle_core::Error::Msg(
+            "no GPU backend in this build".to_string(),
+        )),
+    };
+    match gpu {
+        Ok(device) => Ok(device),
+        Err(e) if preference == DevicePreference::Auto => {
+            log::warn!("GPU unavailable, falling back to CPU: {}", e);
+            Ok((candle_core::Device::Cpu, ComputeDevice::Cpu))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
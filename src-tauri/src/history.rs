@@ -0,0 +1,557 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::recorder::Recording;
+use crate::session::Segment;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    version INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS segments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    start_ms INTEGER NOT NULL,
+    end_ms INTEGER NOT NULL,
+    original_text TEXT NOT NULL,
+    translated_text TEXT NOT NULL,
+    track TEXT NOT NULL DEFAULT 'them',
+    version INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS segments_session ON segments(session_id, start_ms);
+CREATE TABLE IF NOT EXISTS recordings (
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    track TEXT NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY (session_id, track)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS segments_fts USING fts5(
+    original_text, translated_text,
+    content = 'segments', content_rowid = 'id', tokenize = 'trigram'
+);
+CREATE TRIGGER IF NOT EXISTS segments_ai AFTER INSERT ON segments BEGIN
+    INSERT INTO segments_fts(rowid, original_text, translated_text)
+    VALUES (new.id, new.original_text, new.translated_text);
+END;
+CREATE TRIGGER IF NOT EXISTS segments_ad AFTER DELETE ON segments BEGIN
+    INSERT INTO segments_fts(segments_fts, rowid, original_text, translated_text)
+    VALUES ('delete', old.id, old.original_text, old.translated_text);
+END;
+";
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: i64,
+    /// Unix time in milliseconds
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    /// Segments of this version are the ones shown, older ones are kept
+    pub version: i64,
+    pub segment_count: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredSegment {
+    pub id: i64,
+    pub session_id: i64,
+    #[serde(flatten)]
+    pub segment: Segment,
+}
+
+/// Transcripts of all recordings, kept in SQLite under the app data directory.
+pub struct History {
+    conn: Connection,
+    current: Option<i64>,
+}
+
+impl History {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> anyhow::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)?;
+        // Sessions left open by a crash are closed at their last segment
+        conn.execute(
+            "UPDATE sessions SET ended_at = started_at + COALESCE(
+                (SELECT MAX(end_ms) FROM segments WHERE session_id = sessions.id), 0)
+             WHERE ended_at IS NULL",
+            [],
+        )?;
+        Ok(Self {
+            conn,
+            current: None,
+        })
+    }
+
+    /// Opens a new session that subsequent segments are written to.
+    pub fn start_session(&mut self) -> anyhow::Result<i64> {
+        self.end_session()?;
+        self.conn.execute(
+            "INSERT INTO sessions (started_at) VALUES (?1)",
+            params![now_ms()],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        self.current = Some(id);
+        Ok(id)
+    }
+
+    pub fn end_session(&mut self) -> anyhow::Result<()> {
+        if let Some(id) = self.current.take() {
+            self.conn.execute(
+                "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
+                params![now_ms(), id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The session being recorded, if any.
+    pub fn current_session(&self) -> Option<i64> {
+        self.current
+    }
+
+    /// Saves a live segment to the session it was recorded in, which may
+    /// already have ended.
+    pub fn add_segment(&self, session_id: i64, segment: &Segment) -> anyhow::Result<()> {
+        Self::insert_segment(&self.conn, session_id, segment)
+    }
+
+    fn insert_segment(conn: &Connection, session_id: i64, segment: &Segment) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT INTO segments
+                (session_id, start_ms, end_ms, original_text, translated_text, track, version)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, (SELECT version FROM sessions WHERE id = ?1))",
+            params![
+                session_id,
+                segment.start_ms,
+                segment.end_ms,
+                segment.original_text,
+                segment.translated_text,
+                segment.track.as_str()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a new transcript of a session, either over the shown segments or
+    /// as a new version that keeps the old ones. Returns the version stored.
+    pub fn save_version(
+        &mut self,
+        session_id: i64,
+        segments: &[Segment],
+        replace: bool,
+    ) -> anyhow::Result<i64> {
+        let tx = self.conn.transaction()?;
+        if replace {
+            tx.execute(
+                "DELETE FROM segments WHERE session_id = ?1
+                 AND version = (SELECT version FROM sessions WHERE id = ?1)",
+                params![session_id],
+            )?;
+        } else {
+            tx.execute(
+                "UPDATE sessions SET version = MAX(version,
+                    (SELECT COALESCE(MAX(version), 0) FROM segments WHERE session_id = ?1)) + 1
+                 WHERE id = ?1",
+                params![session_id],
+            )?;
+        }
+        for segment in segments {
+            Self::insert_segment(&tx, session_id, segment)?;
+        }
+        let version = tx.query_row(
+            "SELECT version FROM sessions WHERE id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
+        Ok(version)
+    }
+
+    pub fn add_recording(&self, session_id: i64, recording: &Recording) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO recordings (session_id, track, path) VALUES (?1, ?2, ?3)",
+            params![
+                session_id,
+                recording.track.as_str(),
+                recording.path.to_string_lossy()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Audio files of a session, one per track.
+    pub fn recordings(&self, session_id: i64) -> anyhow::Result<Vec<Recording>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT track, path FROM recordings WHERE session_id = ?1 ORDER BY track")?;
+        let recordings = stmt
+            .query_map(params![session_id], |row| {
+                Ok(Recording {
+                    track: row.get::<_, String>(0)?.parse().unwrap_or_default(),
+                    path: row.get::<_, String>(1)?.into(),
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(recordings)
+    }
+
+    /// All sessions, newest first.
+    pub fn list_sessions(&self) -> anyhow::Result<Vec<SessionInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, s.started_at, s.ended_at, s.version, COUNT(g.id)
+             FROM sessions s
+             LEFT JOIN segments g ON g.session_id = s.id AND g.version = s.version
+             GROUP BY s.id ORDER BY s.started_at DESC",
+        )?;
+        let sessions = stmt
+            .query_map([], |row| {
+                Ok(SessionInfo {
+                    id: row.get(0)?,
+                    started_at: row.get(1)?,
+                    ended_at: row.get(2)?,
+                    version: row.get(3)?,
+                    segment_count: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(sessions)
+    }
+
+    pub fn session(&self, id: i64) -> anyhow::Result<Option<SessionInfo>> {
+        let session = self
+            .conn
+            .query_row(
+                "SELECT s.id, s.started_at, s.ended_at, s.version,
+                    (SELECT COUNT(*) FROM segments
+                     WHERE session_id = s.id AND version = s.version)
+                 FROM sessions s WHERE s.id = ?1",
+                params![id],
+                |row| {
+                    Ok(SessionInfo {
+                        id: row.get(0)?,
+                        started_at: row.get(1)?,
+                        ended_at: row.get(2)?,
+                        version: row.get(3)?,
+                        segment_count: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(session)
+    }
+
+    /// Segments of a session, of the shown version unless `version` is given.
+    pub fn segments(
+        &self,
+        session_id: i64,
+        version: Option<i64>,
+        offset: u32,
+        limit: u32,
+    ) -> anyhow::Result<Vec<StoredSegment>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, session_id, start_ms, end_ms, original_text, translated_text, track
+             FROM segments WHERE session_id = ?1
+                AND version = COALESCE(?4, (SELECT version FROM sessions WHERE id = ?1))
+             ORDER BY start_ms, id LIMIT ?2 OFFSET ?3",
+        )?;
+        let segments = stmt
+            .query_map(
+                params![session_id, limit, offset, version],
+                Self::read_segment,
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok(segments)
+    }
+
+    /// Segments of any session whose original or translated text contains `query`.
+    pub fn search(&self, query: &str, limit: u32) -> anyhow::Result<Vec<StoredSegment>> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        // The trigram tokenizer cannot match fewer than three characters
+        let mut stmt = if query.chars().count() >= 3 {
+            self.conn.prepare(
+                "SELECT g.id, g.session_id, g.start_ms, g.end_ms, g.original_text,
+                    g.translated_text, g.track
+                 FROM segments_fts f JOIN segments g ON g.id = f.rowid
+                 JOIN sessions s ON s.id = g.session_id AND s.version = g.version
+                 WHERE segments_fts MATCH ?1
+                 ORDER BY g.session_id DESC, g.start_ms LIMIT ?2",
+            )?
+        } else {
+            self.conn.prepare(
+                "SELECT g.id, g.session_id, g.start_ms, g.end_ms, g.original_text,
+                    g.translated_text, g.track
+                 FROM segments g
+                 JOIN sessions s ON s.id = g.session_id AND s.version = g.version
+                 WHERE instr(g.original_text, ?1) > 0 OR instr(g.translated_text, ?1) > 0
+                 ORDER BY g.session_id DESC, g.start_ms LIMIT ?2",
+            )?
+        };
+        let pattern = if query.chars().count() >= 3 {
+            // Quote the query so FTS syntax characters are matched literally
+            format!("\"{}\"", query.replace('"', "\"\""))
+        } else {
+            query.to_string()
+        };
+        let segments = stmt
+            .query_map(params![pattern, limit], Self::read_segment)?
+            .collect::<Result<_, _>>()?;
+        Ok(segments)
+    }
+
+    pub fn delete_session(&mut self, id: i64) -> anyhow::Result<()> {
+        if self.current == Some(id) {
+            self.current = None;
+        }
+        self.conn
+            .execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn delete_segment(&self, id: i64) -> anyhow::Result<()> {
+        self.conn
+            .execute("DELETE FROM segments WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn read_segment(row: &rusqlite::Row) -> rusqlite::Result<StoredSegment> {
+        Ok(StoredSegment {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            segment: Segment {
+                track: row.get::<_, String>(6)?.parse().unwrap_or_default(),
+                start_ms: row.get(2)?,
+                end_ms: row.get(3)?,
+                original_text: row.get(4)?,
+                translated_text: row.get(5)?,
+            },
+        })
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::Track;
+
+    use super::*;
+
+    fn history() -> History {
+        History::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn segment(start_ms: u64, original: &str, translated: &str) -> Segment {
+        Segment {
+            track: Track::Them,
+            start_ms,
+            end_ms: start_ms + 1000,
+            original_text: original.to_string(),
+            translated_text: translated.to_string(),
+        }
+    }
+
+    fn texts(segments: &[StoredSegment]) -> Vec<&str> {
+        segments
+            .iter()
+            .map(|s| s.segment.original_text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn creates_the_schema() {
+        let history = history();
+        let tables: Vec<String> = history
+            .conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        for table in ["recordings", "segments", "segments_fts", "sessions"] {
+            assert!(tables.iter().any(|t| t == table), "{table} missing");
+        }
+        // Opening the same database again leaves it alone
+        let conn = history.conn;
+        conn.execute("INSERT INTO sessions (started_at) VALUES (0)", [])
+            .unwrap();
+        let history = History::with_connection(conn).unwrap();
+        assert_eq!(history.list_sessions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn segments_are_stored_per_session() {
+        let mut history = history();
+        let id = history.start_session().unwrap();
+        assert_eq!(history.current_session(), Some(id));
+        history
+            .add_segment(id, &segment(2000, " Second", " Zweite"))
+            .unwrap();
+        history
+            .add_segment(id, &segment(0, " First", " Erste"))
+            .unwrap();
+        history.end_session().unwrap();
+        assert_eq!(history.current_session(), None);
+
+        let session = history.session(id).unwrap().unwrap();
+        assert!(session.ended_at.is_some());
+        assert_eq!(session.segment_count, 2);
+        let segments = history.segments(id, None, 0, 10).unwrap();
+        assert_eq!(texts(&segments), [" First", " Second"]);
+        assert_eq!(segments[1].segment.translated_text, " Zweite");
+        assert_eq!(
+            texts(&history.segments(id, None, 1, 10).unwrap()),
+            [" Second"]
+        );
+
+        history.delete_session(id).unwrap();
+        assert!(history.session(id).unwrap().is_none());
+        assert!(history.segments(id, None, 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn searches_original_and_translated_text() {
+        let mut history = history();
+        let first = history.start_session().unwrap();
+        history
+            .add_segment(first, &segment(0, " Hello world", " Hallo Welt"))
+            .unwrap();
+        let second = history.start_session().unwrap();
+        history
+            .add_segment(second, &segment(0, " 今日は晴れ", " Sunny today"))
+            .unwrap();
+        history
+            .add_segment(second, &segment(1000, " Say \"hi\"", " Sag \"hallo\""))
+            .unwrap();
+
+        // Trigrams match inside words and across them
+        assert_eq!(
+            texts(&history.search("lo wor", 10).unwrap()),
+            [" Hello world"]
+        );
+        assert_eq!(
+            texts(&history.search("Welt", 10).unwrap()),
+            [" Hello world"]
+        );
+        assert_eq!(
+            texts(&history.search("日は晴", 10).unwrap()),
+            [" 今日は晴れ"]
+        );
+        // Shorter queries fall back to a plain substring search
+        assert_eq!(texts(&history.search("晴れ", 10).unwrap()), [" 今日は晴れ"]);
+        // FTS syntax is matched literally, newest sessions come first
+        assert_eq!(
+            texts(&history.search("\"hi\"", 10).unwrap()),
+            [" Say \"hi\""]
+        );
+        assert_eq!(
+            texts(&history.search("llo", 10).unwrap()),
+            [" Say \"hi\"", " Hello world"]
+        );
+        assert_eq!(history.search("llo", 1).unwrap().len(), 1);
+        assert!(history.search("  ", 10).unwrap().is_empty());
+        assert!(history.search("nothing", 10).unwrap().is_empty());
+
+        let hello = history.search("Hello", 10).unwrap()[0].id;
+        history.delete_segment(hello).unwrap();
+        assert!(history.search("Hello", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn closes_sessions_left_open_by_a_crash() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO sessions (id, started_at) VALUES (1, 10000), (2, 20000);
+             INSERT INTO sessions (id, started_at, ended_at) VALUES (3, 30000, 90000);
+             INSERT INTO segments (session_id, start_ms, end_ms, original_text, translated_text)
+             VALUES (1, 0, 4000, 'a', 'a'), (1, 4000, 7500, 'b', 'b'), (3, 0, 1000, 'c', 'c');",
+        )
+        .unwrap();
+        let history = History::with_connection(conn).unwrap();
+        let ended_at = |id| history.session(id).unwrap().unwrap().ended_at;
+        // At the end of the last segment
+        assert_eq!(ended_at(1), Some(17500));
+        // Right away without any
+        assert_eq!(ended_at(2), Some(20000));
+        // Sessions that were ended properly keep their time
+        assert_eq!(ended_at(3), Some(90000));
+    }
+
+    #[test]
+    fn save_version_replaces_or_keeps_the_shown_segments() {
+        let mut history = history();
+        let id = history.start_session().unwrap();
+        history
+            .add_segment(id, &segment(0, " live", " live"))
+            .unwrap();
+        history
+            .add_segment(id, &segment(1000, " take", " take"))
+            .unwrap();
+
+        let version = history
+            .save_version(id, &[segment(0, " reprocessed", " neu")], false)
+            .unwrap();
+        assert_eq!(version, 1);
+        let session = history.session(id).unwrap().unwrap();
+        assert_eq!((session.version, session.segment_count), (1, 1));
+        assert_eq!(
+            texts(&history.segments(id, None, 0, 10).unwrap()),
+            [" reprocessed"]
+        );
+        // The live transcript is kept but no longer shown or found
+        assert_eq!(
+            texts(&history.segments(id, Some(0), 0, 10).unwrap()),
+            [" live", " take"]
+        );
+        assert!(history.search("live", 10).unwrap().is_empty());
+        // Live segments arriving late belong to the shown version
+        history
+            .add_segment(id, &segment(5000, " late", " late"))
+            .unwrap();
+        assert_eq!(history.session(id).unwrap().unwrap().segment_count, 2);
+
+        let replaced = history
+            .save_version(
+                id,
+                &[
+                    segment(0, " once", " einmal"),
+                    segment(1000, " more", " mehr"),
+                ],
+                true,
+            )
+            .unwrap();
+        assert_eq!(replaced, 1);
+        assert_eq!(
+            texts(&history.segments(id, None, 0, 10).unwrap()),
+            [" once", " more"]
+        );
+        assert_eq!(history.segments(id, Some(0), 0, 10).unwrap().len(), 2);
+        assert!(history.search("reprocessed", 10).unwrap().is_empty());
+        assert_eq!(history.search("once", 10).unwrap().len(), 1);
+
+        assert_eq!(history.save_version(id, &[], false).unwrap(), 2);
+        assert_eq!(history.session(id).unwrap().unwrap().segment_count, 0);
+    }
+}
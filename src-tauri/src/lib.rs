@@ -1,24 +1,51 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::{
     fs,
     sync::{Arc, Mutex},
 };
 
-use audio::AudioOutput;
+use audio::format::ResampleQuality;
+use audio::{
+    AudioDevice, AudioOutput, AudioSourceConfig, AudioWindow, CaptureMode, CaptureSettings,
+    CaptureTarget, Track,
+};
+use captions::{CaptionServer, CaptionServerSettings};
+use device::{ComputeDevice, DevicePreference};
+use history::{History, SessionInfo, StoredSegment};
+use models::{Manifest, ModelManager};
+use pipeline::{Stage, StageStatus, Supervisor};
+use recorder::{Recorder, RecorderEvent, Recording, RecordingFormat};
+use registry::{Registry, TokenizerFile, TranslationPair, WhisperModel};
+use segmenter::{Segmenter, Sentence, Timing};
 use serde::{Deserialize, Serialize};
+use session::{ExportFormat, ExportLanguage, Segment, Session};
+use stabilizer::{Hypothesis, Stabilizer};
 use tauri::{
     menu::{Menu, MenuItem},
     AppHandle, Emitter, Manager, WebviewWindowBuilder,
 };
 use tauri_plugin_store::StoreExt as _;
-use translate::Translator;
+use translate::{ModelConfig, Translator};
 use whisper::Whisper;
 
-mod audio;
-mod translate;
-mod whisper;
+pub mod audio;
+pub mod captions;
+pub mod device;
+pub mod history;
+pub mod models;
+pub mod pipeline;
+pub mod recorder;
+pub mod registry;
+pub mod reprocess;
+pub mod segmenter;
+pub mod sentencepiece;
+pub mod session;
+pub mod stabilizer;
+pub mod translate;
+pub mod vad;
+pub mod whisper;
 
 #[derive(Serialize, Deserialize, Clone)]
 struct ModelInfo {
@@ -26,6 +53,33 @@ struct ModelInfo {
     #[serde(rename = "fileName")]
     file_name: String,
     status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    imported: Option<ImportedModel>,
+}
+
+/// A model from a local file or directory, kept in `models.dat` to register
+/// it again on start.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ImportedModel {
+    Whisper(WhisperModel),
+    Translation(TranslationPair),
+}
+
+impl ImportedModel {
+    fn file_name(&self) -> String {
+        match self {
+            ImportedModel::Whisper(model) => model.file_name.clone(),
+            ImportedModel::Translation(pair) => pair.model_file(),
+        }
+    }
+
+    fn register(self, registry: &mut Registry) {
+        match self {
+            ImportedModel::Whisper(model) => registry.add_whisper_model(model),
+            ImportedModel::Translation(pair) => registry.add_pair(pair),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -33,107 +87,416 @@ struct AppState {
     audio_output: Arc<Mutex<AudioOutput>>,
     whisper: Arc<Mutex<Option<Whisper>>>,
     translator: Arc<Mutex<Option<Translator>>>,
+    device: Arc<Mutex<DevicePreference>>,
+    session: Arc<Mutex<Session>>,
+    history: Arc<Mutex<History>>,
+    language: Arc<Mutex<String>>,
+    registry: Arc<Mutex<Registry>>,
+    translation_pair: Arc<Mutex<String>>,
+    /// File name of the whisper model in use
+    whisper_model: Arc<Mutex<String>>,
+    supervisor: Supervisor,
+    recorder: mpsc::Sender<RecorderEvent>,
+    /// `None` when captured audio isn't kept
+    recording_format: Arc<Mutex<Option<RecordingFormat>>>,
+    models: Arc<ModelManager>,
+    captions: CaptionServer,
+}
+
+struct Transcription {
+    hypothesis: Hypothesis,
+    /// Where whisper placed the pieces of the hypothesis text
+    timings: Vec<Timing>,
+    track: Track,
+    start_ms: u64,
+    end_ms: u64,
+    is_final: bool,
+    language: String,
+    session_id: Option<i64>,
+}
+
+/// A sentence completed or updated by the segmenter.
+struct SentenceUpdate {
+    sentence: Sentence,
+    segment_id: u64,
+    track: Track,
+    language: String,
+    /// History session the sentence is saved to
+    session_id: Option<i64>,
 }
 
 impl AppState {
-    pub fn new(app: AppHandle) -> anyhow::Result<Self> {
+    pub fn new(
+        app: AppHandle,
+        device: DevicePreference,
+        language: String,
+        translation_pair: String,
+        whisper_model: String,
+        imported: Vec<ImportedModel>,
+        capture: CaptureSettings,
+        recording_format: Option<RecordingFormat>,
+    ) -> anyhow::Result<Self> {
         let (audio_sender, audio_receiver) = mpsc::channel();
         let (transcript_sender, transcript_receiver) = mpsc::channel();
-        let audio_output = AudioOutput::new(audio_sender)?;
+        let (sentence_sender, sentence_receiver) = mpsc::channel();
+        let (recorder_sender, recorder_receiver) = mpsc::channel();
+        let audio_output = AudioOutput::new(audio_sender, capture)?;
         let whisper = Arc::new(Mutex::new(None::<Whisper>));
         let whisper_arc = whisper.clone();
         let translator = Arc::new(Mutex::new(None::<Translator>));
         let translator_arc = translator.clone();
+        let session = Arc::new(Mutex::new(Session::default()));
+        let session_arc = session.clone();
+        let app_dir = app.path().app_data_dir()?;
+        fs::create_dir_all(&app_dir)?;
+        let history = Arc::new(Mutex::new(History::open(&app_dir.join("history.db"))?));
+        let mut registry = Registry::load(&app_dir.join("model"));
+        for model in imported {
+            model.register(&mut registry);
+        }
+        let translation_pair = if registry.get(&translation_pair).is_some() {
+            translation_pair
+        } else {
+            log::warn!(
+                "unknown translation pair {}, using default",
+                translation_pair
+            );
+            registry::DEFAULT_PAIR.to_string()
+        };
+        let whisper_model = if registry.whisper_model(&whisper_model).is_some() {
+            whisper_model
+        } else {
+            log::warn!("unknown whisper model {}, using default", whisper_model);
+            registry::DEFAULT_WHISPER_MODEL.to_string()
+        };
+        let history_arc = history.clone();
+        let recorder = Arc::new(Mutex::new(Recorder::new(
+            app_dir.join("recordings"),
+            history.clone(),
+        )));
 
-        std::thread::spawn(move || {
-            while let Ok(samples) = audio_receiver.recv() {
-                let mut whisper = whisper_arc.lock().unwrap();
-                if whisper.is_none() {
-                    continue;
+        let captions = CaptionServer::default();
+        let captions_arc = captions.clone();
+
+        let supervisor = Supervisor::new(app.clone());
+        supervisor.spawn(Stage::Recorder, recorder_receiver, move || {
+            recorder.clear_poison();
+            let recorder = recorder.clone();
+            Box::new(move |event: RecorderEvent| recorder.lock().unwrap().handle(event))
+        });
+        supervisor.spawn(Stage::Whisper, audio_receiver, move || {
+            // A crashed worker may have left the lock poisoned
+            whisper_arc.clear_poison();
+            let whisper_arc = whisper_arc.clone();
+            let transcript_sender = transcript_sender.clone();
+            // Tracks are transcribed independently, each with its own segments
+            let mut stabilizers = HashMap::<Track, Stabilizer>::new();
+            let mut recording = None;
+            Box::new(move |window: AudioWindow| {
+                // Each recording starts with new utterances
+                if window.session_id != recording {
+                    stabilizers.clear();
+                    recording = window.session_id;
                 }
-                let text = whisper.as_mut().unwrap().transcribe(samples).unwrap();
-                transcript_sender.send(text).unwrap();
-            }
+                let mut whisper = whisper_arc.lock().unwrap();
+                let Some(whisper) = whisper.as_mut() else {
+                    return Ok(());
+                };
+                let window_start_ms = window.start as u64 * 1000 / 16000;
+                let window_end_ms = window.end as u64 * 1000 / 16000;
+                let mut transcript = whisper.transcribe(window.samples)?;
+                transcript.segments.retain(|s| !s.is_likely_silence());
+                // Narrow the window down to where whisper heard speech
+                let (start_ms, end_ms) = transcript.speech_span(window_end_ms - window_start_ms);
+                let (start_ms, end_ms) = (window_start_ms + start_ms, window_start_ms + end_ms);
+                let text = transcript.speech_text();
+                let stabilizer = stabilizers.entry(window.track).or_default();
+                let hypothesis = match (window.is_final, text) {
+                    (true, text) => stabilizer.finish(text.as_deref()),
+                    (false, Some(text)) => Some(stabilizer.update(&text)),
+                    (false, None) => None,
+                };
+                let Some(hypothesis) = hypothesis else {
+                    return Ok(());
+                };
+                // Whisper's timings are for its own text, the segmenter cuts
+                // the stabilized one
+                let timings = segmenter::align_timings(
+                    &transcript.timings(window_start_ms),
+                    &transcript.text(),
+                    &hypothesis.text(),
+                );
+                transcript_sender.send(Transcription {
+                    hypothesis,
+                    timings,
+                    track: window.track,
+                    start_ms,
+                    end_ms,
+                    is_final: window.is_final,
+                    language: transcript.language,
+                    session_id: window.session_id,
+                })?;
+                Ok(())
+            })
         });
 
-        std::thread::spawn(move || {
-            while let Ok(text) = transcript_receiver.recv() {
-                let mut translator = translator_arc.lock().unwrap();
-                if translator.is_none() {
-                    continue;
+        supervisor.spawn(Stage::Segmenter, transcript_receiver, move || {
+            let sentence_sender = sentence_sender.clone();
+            let mut segmenters = HashMap::<Track, Segmenter>::new();
+            let mut recording = None;
+            Box::new(move |transcription: Transcription| {
+                // Timestamps count from zero again in each recording
+                if transcription.session_id != recording {
+                    segmenters.values_mut().for_each(Segmenter::reset);
+                    recording = transcription.session_id;
                 }
-                let translated_text = translator.as_mut().unwrap().translate(&text).unwrap();
-                if text == " [BLANK_AUDIO]" {
-                    app.emit(
-                        "event",
-                        Event {
-                            original_text: "BLANK_AUDIO".to_string(),
-                            translated_text: "空白".to_string(),
-                        },
-                    )
-                    .unwrap();
-                    continue;
+                let Transcription {
+                    hypothesis,
+                    timings,
+                    track,
+                    start_ms,
+                    end_ms,
+                    is_final,
+                    language,
+                    session_id,
+                } = transcription;
+                let sentences = segmenters.entry(track).or_default().push(
+                    &hypothesis,
+                    &timings,
+                    (start_ms, end_ms),
+                    is_final,
+                );
+                for sentence in sentences {
+                    sentence_sender.send(SentenceUpdate {
+                        sentence,
+                        segment_id: hypothesis.segment_id,
+                        track,
+                        language: language.clone(),
+                        session_id,
+                    })?;
                 }
-                log::debug!("original_text: {}", text);
-                log::debug!("translated_text: {}", translated_text);
-                app.emit(
-                    "event",
-                    Event {
-                        original_text: text,
-                        translated_text,
-                    },
-                )
-                .unwrap();
-            }
+                Ok(())
+            })
+        });
+
+        supervisor.spawn(Stage::Translator, sentence_receiver, move || {
+            translator_arc.clear_poison();
+            session_arc.clear_poison();
+            history_arc.clear_poison();
+            let translator_arc = translator_arc.clone();
+            let session_arc = session_arc.clone();
+            let history_arc = history_arc.clone();
+            let captions_arc = captions_arc.clone();
+            let app = app.clone();
+            Box::new(move |update: SentenceUpdate| {
+                let SentenceUpdate {
+                    sentence,
+                    segment_id,
+                    track,
+                    language,
+                    session_id,
+                } = update;
+                let text = sentence.original_text();
+                // Sentences in progress are only displayed, Marian does poorly
+                // on half a sentence
+                let translated_text = if sentence.complete {
+                    let mut translator = translator_arc.lock().unwrap();
+                    let Some(translator) = translator.as_mut() else {
+                        return Ok(());
+                    };
+                    let translated_text = translator.translate(&text)?;
+                    log::debug!("original_text: {}", text);
+                    log::debug!("translated_text: {}", translated_text);
+                    let mut session = session_arc.lock().unwrap();
+                    let segment = session.push(Segment {
+                        track,
+                        start_ms: sentence.start_ms,
+                        end_ms: sentence.end_ms,
+                        original_text: text.clone(),
+                        translated_text: translated_text.clone(),
+                    });
+                    // The session may have ended while the sentence was in the
+                    // pipeline, it's saved to the one it was recorded in
+                    if let (Some(segment), Some(session_id)) = (segment, session_id) {
+                        let history = history_arc.lock().unwrap();
+                        if let Err(e) = history.add_segment(session_id, segment) {
+                            log::error!("failed to save segment to history: {}", e);
+                        }
+                    }
+                    translated_text
+                } else {
+                    String::new()
+                };
+                let kind = if sentence.complete {
+                    "final"
+                } else {
+                    "partial"
+                };
+                let event = TranscriptEvent {
+                    segment_id,
+                    sentence_id: sentence.sentence_id,
+                    track,
+                    start_ms: sentence.start_ms,
+                    end_ms: sentence.end_ms,
+                    original_text: text,
+                    committed_text: sentence.text,
+                    tentative_text: sentence.tentative,
+                    translated_text,
+                    language,
+                };
+                captions_arc.publish(kind, &event);
+                app.emit(kind, event)?;
+                Ok(())
+            })
         });
 
         Ok(Self {
             audio_output: Arc::new(Mutex::new(audio_output)),
             whisper,
             translator,
+            device: Arc::new(Mutex::new(device)),
+            session,
+            history,
+            language: Arc::new(Mutex::new(language)),
+            registry: Arc::new(Mutex::new(registry)),
+            translation_pair: Arc::new(Mutex::new(translation_pair)),
+            whisper_model: Arc::new(Mutex::new(whisper_model)),
+            supervisor,
+            recorder: recorder_sender,
+            recording_format: Arc::new(Mutex::new(recording_format)),
+            models: Arc::new(ModelManager::new(Manifest::load(&app_dir.join("model")))),
+            captions,
         })
     }
 
+    fn active_pair(&self) -> Result<TranslationPair, String> {
+        let id = self.translation_pair.lock().unwrap().clone();
+        self.registry
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("Unknown translation pair: {id}"))
+    }
+
     fn is_ready(&self) -> bool {
         self.whisper.lock().unwrap().is_some() && self.translator.lock().unwrap().is_some()
     }
 
     fn set_model(&self, app: &AppHandle, file_name: &str) -> Result<(), String> {
         log::info!("create model: {}", file_name);
-        match file_name {
-            "ggml-base-q5_1.bin" => {
-                self.whisper
-                    .lock()
-                    .unwrap()
-                    .replace(Self::create_whisper(app, file_name)?);
+        let is_whisper = self
+            .registry
+            .lock()
+            .unwrap()
+            .whisper_model(file_name)
+            .is_some();
+        if is_whisper {
+            // Other sizes are only loaded once the user switches to them
+            if file_name != *self.whisper_model.lock().unwrap() {
+                return Ok(());
             }
-            "opus-mt-en-zh.bin" => {
-                self.translator
-                    .lock()
-                    .unwrap()
-                    .replace(Self::create_translator(app, file_name)?);
+            self.whisper
+                .lock()
+                .unwrap()
+                .replace(self.create_whisper(app, file_name)?);
+        } else {
+            let pair = self
+                .registry
+                .lock()
+                .unwrap()
+                .by_model_file(file_name)
+                .cloned()
+                .ok_or_else(|| format!("Unknown model: {file_name}"))?;
+            // Other pairs are only loaded once the user switches to them
+            if pair.id != *self.translation_pair.lock().unwrap() {
+                return Ok(());
             }
-            _ => unreachable!(),
+            self.translator
+                .lock()
+                .unwrap()
+                .replace(self.create_translator(app, &pair)?);
         }
+        app.emit("compute-device", self.device_status())
+            .map_err(|e| e.to_string())?;
         Ok(())
     }
 
-    fn create_whisper(app: &AppHandle, file_name: &str) -> Result<Whisper, String> {
+    /// Drops a model if it's the one in use.
+    fn unload_model(&self, file_name: &str) {
+        if file_name == *self.whisper_model.lock().unwrap() {
+            self.whisper.lock().unwrap().take();
+        } else {
+            let pair = self
+                .registry
+                .lock()
+                .unwrap()
+                .by_model_file(file_name)
+                .map(|pair| pair.id.clone());
+            if pair.as_ref() == Some(&*self.translation_pair.lock().unwrap()) {
+                self.translator.lock().unwrap().take();
+            }
+        }
+    }
+
+    /// Unregisters an imported model, going back to the default one if it was
+    /// selected.
+    fn forget_model(&self, file_name: &str) {
+        let mut registry = self.registry.lock().unwrap();
+        let mut pair = self.translation_pair.lock().unwrap();
+        if registry
+            .get(&pair)
+            .is_some_and(|p| p.model_file() == file_name)
+        {
+            *pair = registry::DEFAULT_PAIR.to_string();
+        }
+        let mut whisper_model = self.whisper_model.lock().unwrap();
+        if *whisper_model == file_name {
+            *whisper_model = registry::DEFAULT_WHISPER_MODEL.to_string();
+        }
+        registry.remove_imported(file_name);
+    }
+
+    fn create_whisper(&self, app: &AppHandle, file_name: &str) -> Result<Whisper, String> {
         let model_dir = model_dir(app)?;
-        let whisper = Whisper::new(model_dir.join(file_name).to_str().unwrap());
+        let mut whisper = Whisper::new(
+            model_dir.join(file_name).to_str().unwrap(),
+            *self.device.lock().unwrap(),
+        )
+        .map_err(|e| e.to_string())?;
+        whisper
+            .set_language(&self.language.lock().unwrap())
+            .map_err(|e| e.to_string())?;
         Ok(whisper)
     }
 
-    fn create_translator(app: &AppHandle, file_name: &str) -> Result<Translator, String> {
-        let model_dir = model_dir(app)?;
-        let (en_token, zh_token) = get_token_path(app);
+    fn create_translator(
+        &self,
+        app: &AppHandle,
+        pair: &TranslationPair,
+    ) -> Result<Translator, String> {
+        let model_path = model_dir(app)?.join(pair.model_file());
+        let source_token = get_token_path(app, &pair.source_tokenizer)?;
+        let target_token = get_token_path(app, &pair.target_tokenizer)?;
+        let config =
+            ModelConfig::load(&model_path, pair.config.as_ref()).map_err(|e| e.to_string())?;
         Translator::new(
-            model_dir.join(file_name).to_str().unwrap(),
-            en_token.to_str().unwrap(),
-            zh_token.to_str().unwrap(),
+            model_path.to_str().unwrap(),
+            config,
+            source_token.to_str().unwrap(),
+            target_token.to_str().unwrap(),
+            *self.device.lock().unwrap(),
         )
         .map_err(|e| e.to_string())
     }
+
+    fn device_status(&self) -> DeviceStatus {
+        DeviceStatus {
+            preference: *self.device.lock().unwrap(),
+            whisper: self.whisper.lock().unwrap().as_ref().map(|w| w.device()),
+            translator: self.translator.lock().unwrap().as_ref().map(|t| t.device()),
+        }
+    }
 }
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -151,6 +514,32 @@ struct Event {
     translated_text: String,
 }
 
+/// Payload of the `partial` and `final` events, one sentence each. A sentence
+/// receives any number of partials, which aren't translated, followed by one
+/// final. Segments are the utterances sentences are cut from; both ids are
+/// counted per track.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TranscriptEvent {
+    segment_id: u64,
+    sentence_id: u64,
+    track: Track,
+    start_ms: u64,
+    end_ms: u64,
+    original_text: String,
+    committed_text: String,
+    tentative_text: String,
+    translated_text: String,
+    language: String,
+}
+
+#[derive(Serialize, Clone)]
+struct DeviceStatus {
+    preference: DevicePreference,
+    whisper: Option<ComputeDevice>,
+    translator: Option<ComputeDevice>,
+}
+
 #[derive(Serialize, Clone)]
 pub struct DownloadProgress {
     #[serde(rename = "fileName")]
@@ -160,6 +549,14 @@ pub struct DownloadProgress {
     downloaded: u64,
 }
 
+/// Payload of the `reprocess-progress` event.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReprocessProgress {
+    session_id: i64,
+    progress: f32,
+}
+
 #[tauri::command]
 async fn start_recording(
     app: AppHandle,
@@ -179,11 +576,32 @@ async fn start_recording(
     )
     .unwrap();
 
-    state
-        .audio_output
+    state.session.lock().unwrap().start();
+    let session_id = state
+        .history
         .lock()
         .unwrap()
-        .start_recording()
+        .start_session()
+        .map_err(|e| e.to_string())?;
+    let mut audio_output = state.audio_output.lock().unwrap();
+    match *state.recording_format.lock().unwrap() {
+        Some(format) => {
+            // Sent before capture starts so no audio of the session is missed
+            let _ = state
+                .recorder
+                .send(RecorderEvent::Start { session_id, format });
+            let recorder = state.recorder.clone();
+            audio_output.set_tap(Some(Box::new(move |track, samples| {
+                let _ = recorder.send(RecorderEvent::Audio {
+                    track,
+                    samples: samples.to_vec(),
+                });
+            })));
+        }
+        None => audio_output.set_tap(None),
+    }
+    audio_output
+        .start_recording(session_id)
         .map_err(|e| e.to_string())?;
     Ok(true)
 }
@@ -191,7 +609,14 @@ async fn start_recording(
 #[tauri::command]
 fn stop_recording(app: AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
     log::info!("stop_recording");
-    state.audio_output.lock().unwrap().stop_recording();
+    let audio_output = state.audio_output.lock().unwrap();
+    audio_output.stop_recording();
+    audio_output.set_tap(None);
+    let _ = state.recorder.send(RecorderEvent::Stop);
+    drop(audio_output);
+    if let Err(e) = state.history.lock().unwrap().end_session() {
+        log::error!("failed to end history session: {}", e);
+    }
     app.emit(
         "event",
         Event {
@@ -203,6 +628,582 @@ fn stop_recording(app: AppHandle, state: tauri::State<'_, AppState>) -> Result<(
     Ok(())
 }
 
+/// Writes the segments of the current (or last) recording to `path`.
+#[tauri::command]
+fn export_session(
+    path: PathBuf,
+    format: ExportFormat,
+    language: Option<ExportLanguage>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("export_session: {:?} as {:?}", path, format);
+    let content = session::export(
+        state.session.lock().unwrap().segments(),
+        format,
+        language.unwrap_or_default(),
+    )
+    .map_err(|e| e.to_string())?;
+    fs::write(&path, content).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_sessions(state: tauri::State<'_, AppState>) -> Result<Vec<SessionInfo>, String> {
+    state
+        .history
+        .lock()
+        .unwrap()
+        .list_sessions()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_session_segments(
+    session_id: i64,
+    version: Option<i64>,
+    offset: u32,
+    limit: u32,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<StoredSegment>, String> {
+    state
+        .history
+        .lock()
+        .unwrap()
+        .segments(session_id, version, offset, limit)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn search_history(
+    query: String,
+    limit: Option<u32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<StoredSegment>, String> {
+    state
+        .history
+        .lock()
+        .unwrap()
+        .search(&query, limit.unwrap_or(100))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_session(session_id: i64, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    log::info!("delete_session: {}", session_id);
+    let mut history = state.history.lock().unwrap();
+    let recordings = history.recordings(session_id).map_err(|e| e.to_string())?;
+    history
+        .delete_session(session_id)
+        .map_err(|e| e.to_string())?;
+    for recording in recordings {
+        if let Err(e) = fs::remove_file(&recording.path) {
+            log::warn!("failed to delete {:?}: {}", recording.path, e);
+        }
+    }
+    Ok(())
+}
+
+/// Transcribes and translates the recorded audio of a session again, with
+/// `whisper_model` from the model directory or the default model. The new
+/// segments become a new version unless `replace` is set.
+#[tauri::command]
+async fn reprocess_session(
+    app: AppHandle,
+    session_id: i64,
+    whisper_model: Option<String>,
+    replace: Option<bool>,
+    state: tauri::State<'_, AppState>,
+) -> Result<SessionInfo, String> {
+    log::info!("reprocess_session: {} with {:?}", session_id, whisper_model);
+    let recordings = {
+        let history = state.history.lock().unwrap();
+        if history.current_session() == Some(session_id) {
+            return Err("The session is still being recorded".to_string());
+        }
+        history.recordings(session_id).map_err(|e| e.to_string())?
+    };
+    if recordings.is_empty() {
+        return Err(format!("Session {session_id} has no recorded audio"));
+    }
+    let whisper_model =
+        whisper_model.unwrap_or_else(|| state.whisper_model.lock().unwrap().clone());
+    let state = state.inner().clone();
+    // Runs beside the live pipeline with models of its own
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut whisper = state.create_whisper(&app, &whisper_model)?;
+        let mut translator = state.create_translator(&app, &state.active_pair()?)?;
+        let segments =
+            reprocess::reprocess(&recordings, &mut whisper, &mut translator, |progress| {
+                let _ = app.emit(
+                    "reprocess-progress",
+                    ReprocessProgress {
+                        session_id,
+                        progress,
+                    },
+                );
+            })
+            .map_err(|e| e.to_string())?;
+        let mut history = state.history.lock().unwrap();
+        history
+            .save_version(session_id, &segments, replace.unwrap_or(false))
+            .map_err(|e| e.to_string())?;
+        app.emit(
+            "reprocess-progress",
+            ReprocessProgress {
+                session_id,
+                progress: 100.0,
+            },
+        )
+        .map_err(|e| e.to_string())?;
+        history
+            .session(session_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Unknown session: {session_id}"))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Audio files kept for a session, to replay a passage at a segment's `startMs`.
+#[tauri::command]
+fn get_session_recordings(
+    session_id: i64,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<Recording>, String> {
+    state
+        .history
+        .lock()
+        .unwrap()
+        .recordings(session_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_segment(segment_id: i64, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state
+        .history
+        .lock()
+        .unwrap()
+        .delete_segment(segment_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_language(state: tauri::State<'_, AppState>) -> String {
+    state.language.lock().unwrap().clone()
+}
+
+/// Sets the spoken language, an ISO 639-1 code or "auto" to detect it.
+#[tauri::command]
+fn set_language(
+    app: AppHandle,
+    language: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_language: {}", language);
+    if let Some(whisper) = state.whisper.lock().unwrap().as_mut() {
+        whisper.set_language(&language).map_err(|e| e.to_string())?;
+    }
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set("language", language.clone());
+    *state.language.lock().unwrap() = language;
+    Ok(())
+}
+
+#[tauri::command]
+fn set_audio_source(
+    config: AudioSourceConfig,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_audio_source: {:?}", config);
+    state
+        .audio_output
+        .lock()
+        .unwrap()
+        .set_source(&config)
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Serialize, Clone)]
+struct AudioDeviceList {
+    devices: Vec<AudioDevice>,
+    /// `None` when following the system default
+    selected: Option<String>,
+    /// Microphone of dual and mixed mode, `None` when following the system default
+    mic: Option<String>,
+}
+
+/// Outputs, monitors and inputs on Windows and Linux. macOS lists only
+/// displays: ScreenCaptureKit captures system audio per display and always
+/// records the default microphone.
+#[tauri::command]
+fn list_audio_devices(state: tauri::State<'_, AppState>) -> Result<AudioDeviceList, String> {
+    let devices = audio::list_devices().map_err(|e| e.to_string())?;
+    let audio_output = state.audio_output.lock().unwrap();
+    let settings = audio_output.settings();
+    Ok(AudioDeviceList {
+        devices,
+        selected: settings.device.clone(),
+        mic: settings.mic_device.clone(),
+    })
+}
+
+#[tauri::command]
+fn select_audio_device(
+    app: AppHandle,
+    id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("select_audio_device: {:?}", id);
+    let mut audio_output = state.audio_output.lock().unwrap();
+    let settings = CaptureSettings {
+        device: id.clone(),
+        ..audio_output.settings().clone()
+    };
+    audio_output
+        .set_capture_settings(settings)
+        .map_err(|e| e.to_string())?;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set("audio_device", id);
+    Ok(())
+}
+
+#[tauri::command]
+fn select_mic_device(
+    app: AppHandle,
+    id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("select_mic_device: {:?}", id);
+    let mut audio_output = state.audio_output.lock().unwrap();
+    let settings = CaptureSettings {
+        mic_device: id.clone(),
+        ..audio_output.settings().clone()
+    };
+    audio_output
+        .set_capture_settings(settings)
+        .map_err(|e| e.to_string())?;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set("mic_device", id);
+    Ok(())
+}
+
+#[derive(Serialize, Clone)]
+struct CaptureTargetList {
+    targets: Vec<CaptureTarget>,
+    /// `None` when capturing everything played
+    selected: Option<String>,
+}
+
+#[tauri::command]
+fn list_capture_targets(state: tauri::State<'_, AppState>) -> Result<CaptureTargetList, String> {
+    let targets = audio::list_targets().map_err(|e| e.to_string())?;
+    let selected = state.audio_output.lock().unwrap().settings().target.clone();
+    Ok(CaptureTargetList { targets, selected })
+}
+
+#[tauri::command]
+fn select_capture_target(
+    app: AppHandle,
+    id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("select_capture_target: {:?}", id);
+    let mut audio_output = state.audio_output.lock().unwrap();
+    let settings = CaptureSettings {
+        target: id.clone(),
+        ..audio_output.settings().clone()
+    };
+    audio_output
+        .set_capture_settings(settings)
+        .map_err(|e| e.to_string())?;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set("capture_target", id);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_capture_mode(state: tauri::State<'_, AppState>) -> CaptureMode {
+    state.audio_output.lock().unwrap().settings().mode
+}
+
+#[tauri::command]
+fn set_capture_mode(
+    app: AppHandle,
+    mode: CaptureMode,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_capture_mode: {:?}", mode);
+    let mut audio_output = state.audio_output.lock().unwrap();
+    let settings = CaptureSettings {
+        mode,
+        ..audio_output.settings().clone()
+    };
+    audio_output
+        .set_capture_settings(settings)
+        .map_err(|e| e.to_string())?;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set(
+        "capture_mode",
+        serde_json::to_value(mode).map_err(|e| e.to_string())?,
+    );
+    Ok(())
+}
+
+#[tauri::command]
+fn get_resample_quality(state: tauri::State<'_, AppState>) -> ResampleQuality {
+    state.audio_output.lock().unwrap().settings().quality
+}
+
+#[tauri::command]
+fn set_resample_quality(
+    app: AppHandle,
+    quality: ResampleQuality,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_resample_quality: {:?}", quality);
+    let mut audio_output = state.audio_output.lock().unwrap();
+    let settings = CaptureSettings {
+        quality,
+        ..audio_output.settings().clone()
+    };
+    audio_output
+        .set_capture_settings(settings)
+        .map_err(|e| e.to_string())?;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set(
+        "resample_quality",
+        serde_json::to_value(quality).map_err(|e| e.to_string())?,
+    );
+    Ok(())
+}
+
+#[tauri::command]
+fn get_recording_format(state: tauri::State<'_, AppState>) -> Option<RecordingFormat> {
+    *state.recording_format.lock().unwrap()
+}
+
+/// Takes effect from the next recording.
+#[tauri::command]
+fn set_recording_format(
+    app: AppHandle,
+    format: Option<RecordingFormat>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_recording_format: {:?}", format);
+    *state.recording_format.lock().unwrap() = format;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set(
+        "recording_format",
+        serde_json::to_value(format).map_err(|e| e.to_string())?,
+    );
+    Ok(())
+}
+
+#[tauri::command]
+fn pipeline_status(state: tauri::State<'_, AppState>) -> Vec<StageStatus> {
+    state.supervisor.status()
+}
+
+#[tauri::command]
+fn get_compute_device(state: tauri::State<'_, AppState>) -> DeviceStatus {
+    state.device_status()
+}
+
+#[tauri::command]
+async fn set_device_preference(
+    app: AppHandle,
+    preference: DevicePreference,
+    state: tauri::State<'_, AppState>,
+) -> Result<DeviceStatus, String> {
+    log::info!("set_device_preference: {:?}", preference);
+    *state.device.lock().unwrap() = preference;
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set(
+        "device",
+        serde_json::to_value(preference).map_err(|e| e.to_string())?,
+    );
+
+    // Reload the models that are already in use on the new device
+    if state.whisper.lock().unwrap().is_some() {
+        let whisper_model = state.whisper_model.lock().unwrap().clone();
+        state.set_model(&app, &whisper_model)?;
+    }
+    if state.translator.lock().unwrap().is_some() {
+        state.set_model(&app, &state.active_pair()?.model_file())?;
+    }
+    let status = state.device_status();
+    app.emit("compute-device", status.clone())
+        .map_err(|e| e.to_string())?;
+    Ok(status)
+}
+
+#[derive(Serialize, Clone)]
+struct TranslationPairInfo {
+    #[serde(flatten)]
+    pair: TranslationPair,
+    downloaded: bool,
+    active: bool,
+}
+
+#[tauri::command]
+fn list_translation_pairs(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<TranslationPairInfo>, String> {
+    let model_dir = model_dir(&app)?;
+    let active = state.translation_pair.lock().unwrap().clone();
+    let pairs = state
+        .registry
+        .lock()
+        .unwrap()
+        .pairs()
+        .iter()
+        .map(|pair| TranslationPairInfo {
+            downloaded: model_dir.join(pair.model_file()).exists(),
+            active: pair.id == active,
+            pair: pair.clone(),
+        })
+        .collect();
+    Ok(pairs)
+}
+
+/// Switches translation to another registered pair, which must already be downloaded.
+#[tauri::command]
+async fn set_translation_pair(
+    app: AppHandle,
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_translation_pair: {}", id);
+    let pair = state
+        .registry
+        .lock()
+        .unwrap()
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| format!("Unknown translation pair: {id}"))?;
+    if !model_dir(&app)?.join(pair.model_file()).exists() {
+        return Err(format!("Model for {id} is not downloaded"));
+    }
+    let translator = state.create_translator(&app, &pair)?;
+    state.translator.lock().unwrap().replace(translator);
+    *state.translation_pair.lock().unwrap() = id.clone();
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set("translation_pair", id);
+    app.emit("compute-device", state.device_status())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[derive(Serialize, Clone)]
+struct WhisperModelInfo {
+    #[serde(flatten)]
+    model: WhisperModel,
+    downloaded: bool,
+    active: bool,
+}
+
+#[tauri::command]
+fn list_whisper_models(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<WhisperModelInfo>, String> {
+    let model_dir = model_dir(&app)?;
+    let active = state.whisper_model.lock().unwrap().clone();
+    let models = state
+        .registry
+        .lock()
+        .unwrap()
+        .whisper_models()
+        .iter()
+        .map(|model| WhisperModelInfo {
+            downloaded: model_dir.join(&model.file_name).exists(),
+            active: model.file_name == active,
+            model: model.clone(),
+        })
+        .collect();
+    Ok(models)
+}
+
+/// Switches transcription to another whisper model, which must already be
+/// downloaded. A running session continues with the new model.
+#[tauri::command]
+async fn set_whisper_model(
+    app: AppHandle,
+    file_name: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("set_whisper_model: {}", file_name);
+    if state
+        .registry
+        .lock()
+        .unwrap()
+        .whisper_model(&file_name)
+        .is_none()
+    {
+        return Err(format!("Unknown whisper model: {file_name}"));
+    }
+    if !model_dir(&app)?.join(&file_name).exists() {
+        return Err(format!("Model {file_name} is not downloaded"));
+    }
+    let whisper = {
+        let state = state.inner().clone();
+        let app = app.clone();
+        let file_name = file_name.clone();
+        // Loading the larger models takes a few seconds
+        tauri::async_runtime::spawn_blocking(move || state.create_whisper(&app, &file_name))
+            .await
+            .map_err(|e| e.to_string())??
+    };
+    state.whisper.lock().unwrap().replace(whisper);
+    *state.whisper_model.lock().unwrap() = file_name.clone();
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set("whisper_model", file_name);
+    app.emit("compute-device", state.device_status())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_caption_server(state: tauri::State<'_, AppState>) -> Option<CaptionServerSettings> {
+    state.captions.settings()
+}
+
+/// Starts or stops the caption server for other programs. Without a token
+/// a new one is generated.
+#[tauri::command]
+async fn set_caption_server(
+    app: AppHandle,
+    enabled: bool,
+    port: Option<u16>,
+    token: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<CaptionServerSettings>, String> {
+    log::info!("set_caption_server: {} on {:?}", enabled, port);
+    let settings = if enabled {
+        let port = port.unwrap_or(captions::DEFAULT_PORT);
+        let settings = match token.filter(|token| !token.is_empty()) {
+            Some(token) => CaptionServerSettings { port, token },
+            None => CaptionServerSettings::new(port),
+        };
+        state
+            .captions
+            .start(settings.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        Some(settings)
+    } else {
+        state.captions.stop();
+        None
+    };
+    let store = app.store("settings.dat").map_err(|e| e.to_string())?;
+    store.set(
+        "caption_server",
+        serde_json::to_value(&settings).map_err(|e| e.to_string())?,
+    );
+    Ok(settings)
+}
+
 #[tauri::command]
 async fn open_settings(app: AppHandle) -> Result<(), String> {
     // Check if settings window already exists and focus it
@@ -283,55 +1284,80 @@ async fn close_history(app: AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// Downloads a built-in model with the tokenizers and configs that belong to
+/// it. The url comes from the registry, `file_name` only picks the model.
 #[tauri::command]
 async fn download_model(
     app: AppHandle,
-    url: String,
     file_name: String,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), String> {
+    let (url, pair) = {
+        let registry = state.registry.lock().unwrap();
+        let Some(url) = registry.download_url(&file_name) else {
+            return Err(format!("{file_name} is not a model that can be downloaded"));
+        };
+        let pair = registry.by_model_file(&file_name).cloned();
+        (url.to_string(), pair)
+    };
     let model_dir = model_dir(&app)?;
     let file_path = model_dir.join(&file_name);
-    log::info!("download model save to: {}", file_path.to_str().unwrap());
+    log::info!("download model {} save to: {}", url, file_path.display());
     let mut total_size = 0;
     let mut downloaded = 0;
 
     if file_path.exists() {
-        log::info!("model already exists: {}", file_path.to_str().unwrap());
+        log::info!("model already exists: {}", file_path.display());
     } else {
-        // Download the file
-        let response = reqwest::get(&url).await.map_err(|e| e.to_string())?;
-
-        total_size = response
-            .content_length()
-            .ok_or_else(|| "Failed to get content length".to_string())?;
-        let mut file = fs::File::create(&file_path).map_err(|e| e.to_string())?;
-        let mut stream = response.bytes_stream();
-
-        use futures_util::StreamExt;
         let mut last_update = std::time::Instant::now();
-        while let Some(item) = stream.next().await {
-            let chunk = item.map_err(|e| e.to_string())?;
-            std::io::copy(&mut chunk.as_ref(), &mut file).map_err(|e| e.to_string())?;
-            downloaded += chunk.len() as u64;
-
+        let on_progress = |done: u64, total: u64| {
+            downloaded = done;
+            total_size = total;
             // Only update progress every 200ms
             let now = std::time::Instant::now();
-            if now.duration_since(last_update).as_millis() >= 200 {
-                let progress = (downloaded as f32 / total_size as f32) * 100.0;
+            if total > 0 && now.duration_since(last_update).as_millis() >= 200 {
+                let progress = (done as f32 / total as f32) * 100.0;
                 log::info!("download progress: {} %", progress);
-                app.emit(
+                let _ = app.emit(
                     "download-progress",
                     DownloadProgress {
                         file_name: file_name.clone(),
                         progress,
-                        total_size,
-                        downloaded,
+                        total_size: total,
+                        downloaded: done,
                     },
-                )
-                .map_err(|e| e.to_string())?;
+                );
                 last_update = now;
             }
+        };
+        let result = if pair.as_ref().is_some_and(|pair| pair.pytorch_weights) {
+            state
+                .models
+                .download_converted(
+                    &url,
+                    &model_dir,
+                    &file_name,
+                    |checkpoint, weights| {
+                        translate::convert_pytorch(checkpoint, weights)?;
+                        Ok(fs::remove_file(checkpoint)?)
+                    },
+                    on_progress,
+                )
+                .await
+        } else {
+            state
+                .models
+                .download(&url, &model_dir, &file_name, on_progress)
+                .await
+        };
+        result.map_err(|e| e.to_string())?;
+    }
+    if let Some(pair) = pair {
+        for tokenizer in [&pair.source_tokenizer, &pair.target_tokenizer] {
+            download_tokenizer(&model_dir, tokenizer).await?;
+        }
+        for (file_name, url) in pair.config_files() {
+            download_small_file(&model_dir, url, &file_name).await?;
         }
     }
     state.set_model(&app, &file_name)?;
@@ -350,12 +1376,239 @@ async fn download_model(
     Ok(())
 }
 
-fn get_token_path(app: &AppHandle) -> (PathBuf, PathBuf) {
-    let resource_dir = app.path().resource_dir().unwrap();
-    let model_dir = resource_dir.join("model");
-    let en_token = model_dir.join("tokenizer-marian-base-en.json");
-    let zh_token = model_dir.join("tokenizer-marian-base-zh.json");
-    (en_token, zh_token)
+/// Stops a running model download. What was downloaded so far is kept, so
+/// downloading the model again continues where it stopped.
+#[tauri::command]
+async fn cancel_download(
+    file_name: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("cancel_download: {:?}", file_name);
+    if !state.models.cancel(&file_name) {
+        return Err(format!("{file_name} is not being downloaded"));
+    }
+    Ok(())
+}
+
+/// Removes a downloaded model, or what was downloaded of it, unloading it
+/// first if it's in use.
+#[tauri::command]
+async fn delete_model(
+    app: AppHandle,
+    file_name: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    log::info!("delete_model: {:?}", file_name);
+    if state.models.is_downloading(&file_name) {
+        return Err(format!("{file_name} is being downloaded"));
+    }
+    if state.history.lock().unwrap().current_session().is_some() {
+        return Err("Stop recording before deleting a model".to_string());
+    }
+    let store = app.store("models.dat").map_err(|e| e.to_string())?;
+    let mut models: HashMap<String, ModelInfo> = store
+        .get("models")
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default();
+    let imported = models
+        .get(&file_name)
+        .is_some_and(|model| model.imported.is_some());
+    // Only files the app downloaded itself are deleted, `file_name` comes from
+    // the frontend and must not point anywhere else
+    let downloaded = state
+        .registry
+        .lock()
+        .unwrap()
+        .download_url(&file_name)
+        .is_some();
+    if !imported && !downloaded {
+        return Err(format!("{file_name} is not a downloaded model"));
+    }
+    state.unload_model(&file_name);
+    if imported {
+        // Only forget it, the files belong to the user
+        state.forget_model(&file_name);
+        models.remove(&file_name);
+    } else {
+        let model_dir = model_dir(&app)?;
+        for path in [
+            model_dir.join(&file_name),
+            models::part_path(&model_dir, &file_name),
+        ] {
+            if path.exists() {
+                fs::remove_file(&path).map_err(|e| e.to_string())?;
+            }
+        }
+        if let Some(model) = models.get_mut(&file_name) {
+            model.status = "idle".to_string();
+        }
+    }
+    store.set(
+        "models",
+        serde_json::to_value(&models).map_err(|e| e.to_string())?,
+    );
+    app.emit("compute-device", state.device_status())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Languages and tokenizers of an imported translation model.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ImportTranslation {
+    source: String,
+    target: String,
+    source_tokenizer: PathBuf,
+    target_tokenizer: PathBuf,
+}
+
+/// Registers a ggml whisper model, or with `translation` a Marian safetensors
+/// file or directory, once it's checked to load. The files stay where they are.
+#[tauri::command]
+async fn import_model(
+    app: AppHandle,
+    path: PathBuf,
+    translation: Option<ImportTranslation>,
+    state: tauri::State<'_, AppState>,
+) -> Result<ModelInfo, String> {
+    log::info!("import_model: {:?} {:?}", path, translation);
+    let canonical =
+        |path: &Path| fs::canonicalize(path).map_err(|e| format!("{}: {}", path.display(), e));
+    let path = canonical(&path)?;
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let imported = match translation {
+        None => {
+            if !path.is_file() {
+                return Err(format!("{} is not a ggml model file", path.display()));
+            }
+            let len = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+            ImportedModel::Whisper(WhisperModel {
+                file_name: path.to_string_lossy().into_owned(),
+                size: name.clone(),
+                quantization: None,
+                url: String::new(),
+                size_mb: (len / 1_000_000) as u32,
+                imported: true,
+            })
+        }
+        Some(translation) => {
+            let existing = state.registry.lock().unwrap().get(&name).cloned();
+            if existing.is_some_and(|pair| pair.path.is_none()) {
+                return Err(format!("A translation pair named {name} already exists"));
+            }
+            let tokenizer = |path: &Path| -> Result<TokenizerFile, String> {
+                Ok(TokenizerFile {
+                    file_name: canonical(path)?.to_string_lossy().into_owned(),
+                    url: None,
+                    sentencepiece: None,
+                })
+            };
+            ImportedModel::Translation(TranslationPair {
+                id: name.clone(),
+                source: translation.source,
+                target: translation.target,
+                model_url: String::new(),
+                pytorch_weights: false,
+                source_tokenizer: tokenizer(&translation.source_tokenizer)?,
+                target_tokenizer: tokenizer(&translation.target_tokenizer)?,
+                config_url: None,
+                generation_config_url: None,
+                config: None,
+                path: Some(path.clone()),
+            })
+        }
+    };
+
+    // Loading it is the only reliable check that it's a model of that kind
+    {
+        let state = state.inner().clone();
+        let app = app.clone();
+        let imported = imported.clone();
+        tauri::async_runtime::spawn_blocking(move || match &imported {
+            ImportedModel::Whisper(model) => state.create_whisper(&app, &model.file_name).map(drop),
+            ImportedModel::Translation(pair) => state.create_translator(&app, pair).map(drop),
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+    }
+
+    let info = ModelInfo {
+        name,
+        file_name: imported.file_name(),
+        status: "completed".to_string(),
+        imported: Some(imported.clone()),
+    };
+    imported.register(&mut state.registry.lock().unwrap());
+    let store = app.store("models.dat").map_err(|e| e.to_string())?;
+    let mut models: HashMap<String, ModelInfo> = store
+        .get("models")
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default();
+    models.insert(info.file_name.clone(), info.clone());
+    store.set(
+        "models",
+        serde_json::to_value(&models).map_err(|e| e.to_string())?,
+    );
+    Ok(info)
+}
+
+/// Downloads tokenizers and configs that belong to a model, without progress events.
+async fn download_small_file(model_dir: &Path, url: &str, file_name: &str) -> Result<(), String> {
+    let path = model_dir.join(file_name);
+    if path.exists() {
+        return Ok(());
+    }
+    log::info!("download {} save to: {}", url, path.display());
+    let bytes = fetch_small_file(url).await?;
+    fs::write(&path, bytes).map_err(|e| e.to_string())
+}
+
+async fn fetch_small_file(url: &str) -> Result<Vec<u8>, String> {
+    let bytes = reqwest::get(url)
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(bytes.to_vec())
+}
+
+/// Downloads a tokenizer, or builds it from the sentencepiece model of a
+/// model that comes without one.
+async fn download_tokenizer(model_dir: &Path, tokenizer: &TokenizerFile) -> Result<(), String> {
+    if let Some(url) = &tokenizer.url {
+        return download_small_file(model_dir, url, &tokenizer.file_name).await;
+    }
+    let Some(files) = &tokenizer.sentencepiece else {
+        return Ok(());
+    };
+    let path = model_dir.join(&tokenizer.file_name);
+    if path.exists() {
+        return Ok(());
+    }
+    log::info!("build {} from {}", path.display(), files.model_url);
+    let spm = fetch_small_file(&files.model_url).await?;
+    let vocab: HashMap<String, u32> =
+        serde_json::from_slice(&fetch_small_file(&files.vocab_url).await?)
+            .map_err(|e| e.to_string())?;
+    let json = sentencepiece::convert(&spm, &vocab).map_err(|e| e.to_string())?;
+    fs::write(&path, json).map_err(|e| e.to_string())
+}
+
+fn get_token_path(app: &AppHandle, tokenizer: &TokenizerFile) -> Result<PathBuf, String> {
+    let dir = if tokenizer.is_bundled() {
+        app.path()
+            .resource_dir()
+            .map_err(|e| e.to_string())?
+            .join("model")
+    } else {
+        model_dir(app)?
+    };
+    Ok(dir.join(&tokenizer.file_name))
 }
 
 fn model_dir(app: &AppHandle) -> Result<PathBuf, String> {
@@ -385,8 +1638,31 @@ async fn verify_models(app: AppHandle) -> Result<(), String> {
         let model_path = model_dir.join(&model_info.file_name);
         let file_exists = model_path.exists();
 
+        // Imported files may have been moved or deleted since
+        if model_info.imported.is_some() && !file_exists {
+            log::warn!("Imported model {} is gone, forgetting it", file_name);
+            if let Some(state) = app.try_state::<AppState>() {
+                state.unload_model(file_name);
+                state.forget_model(file_name);
+            }
+            models.remove(file_name);
+            needs_update = true;
+        }
+        // Files only appear once their download is complete and verified, a
+        // download in progress is still a `.part` file
+        else if model_info.status == "downloading"
+            && !app
+                .try_state::<AppState>()
+                .is_some_and(|state| state.models.is_downloading(file_name))
+        {
+            log::warn!("Model {} download was interrupted", file_name);
+            if let Some(model) = models.get_mut(file_name) {
+                model.status = if file_exists { "completed" } else { "idle" }.to_string();
+            }
+            needs_update = true;
+        }
         // If model is marked as completed but file doesn't exist, reset status
-        if model_info.status == "completed" && !file_exists {
+        else if model_info.status == "completed" && !file_exists {
             log::warn!(
                 "Model {} marked as completed but file doesn't exist, resetting status",
                 file_name
@@ -494,8 +1770,68 @@ pub fn run() {
                     .unwrap();
             }
 
-            let app_state = AppState::new(app.handle().clone())?;
-
+            let device: DevicePreference = app
+                .store("settings.dat")?
+                .get("device")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .unwrap_or_default();
+            let language: String = app
+                .store("settings.dat")?
+                .get("language")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .unwrap_or_else(|| "en".to_string());
+            let translation_pair: String = app
+                .store("settings.dat")?
+                .get("translation_pair")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .unwrap_or_else(|| registry::DEFAULT_PAIR.to_string());
+            let whisper_model: String = app
+                .store("settings.dat")?
+                .get("whisper_model")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .unwrap_or_else(|| registry::DEFAULT_WHISPER_MODEL.to_string());
+            let audio_device: Option<String> = app
+                .store("settings.dat")?
+                .get("audio_device")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .flatten();
+            let capture_target: Option<String> = app
+                .store("settings.dat")?
+                .get("capture_target")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .flatten();
+            let mic_device: Option<String> = app
+                .store("settings.dat")?
+                .get("mic_device")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .flatten();
+            let resample_quality: ResampleQuality = app
+                .store("settings.dat")?
+                .get("resample_quality")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .unwrap_or_default();
+            let capture_mode: CaptureMode = app
+                .store("settings.dat")?
+                .get("capture_mode")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .unwrap_or_default();
+            let recording_format: Option<RecordingFormat> = app
+                .store("settings.dat")?
+                .get("recording_format")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .flatten();
+            let caption_server: Option<CaptionServerSettings> = app
+                .store("settings.dat")?
+                .get("caption_server")
+                .and_then(|v| serde_json::from_value(v).ok())
+                .flatten();
+            log::info!(
+                "device preference: {:?}, language: {}, translation pair: {}, audio device: {:?}",
+                device,
+                language,
+                translation_pair,
+                audio_device
+            );
             let model_dir = model_dir(app.handle())?;
 
             // Get model store state
@@ -505,33 +1841,72 @@ pub fn run() {
                 .unwrap_or(serde_json::Value::Array(vec![]));
             let mut models: HashMap<String, ModelInfo> =
                 serde_json::from_value(models).unwrap_or_default();
+            let imported = models
+                .values()
+                .filter_map(|model| model.imported.clone())
+                .collect();
 
-            if let Some(info) = models.get("ggml-base-q5_1.bin") {
-                let model_path = model_dir.join(&info.file_name);
-                if info.status == "completed" && model_path.exists() {
-                    let whisper = Whisper::new(model_path.to_str().unwrap());
-                    app_state.whisper.lock().unwrap().replace(whisper);
-                } else {
-                    models.remove("ggml-base-q5_1.bin");
-                    store.set("models", serde_json::to_value(&models).unwrap());
+            let app_state = AppState::new(
+                app.handle().clone(),
+                device,
+                language.clone(),
+                translation_pair,
+                whisper_model,
+                imported,
+                CaptureSettings {
+                    device: audio_device,
+                    target: capture_target,
+                    quality: resample_quality,
+                    mode: capture_mode,
+                    mic_device,
+                },
+                recording_format,
+            )?;
+
+            let whisper_model = app_state.whisper_model.lock().unwrap().clone();
+            // Downloads only leave a file once they are complete
+            let model_path = model_dir.join(&whisper_model);
+            if model_path.exists() {
+                // A model that doesn't load shouldn't keep the app from starting,
+                // another one can be picked in the settings
+                let whisper =
+                    Whisper::new(&model_path.to_string_lossy(), device).and_then(|mut whisper| {
+                        whisper.set_language(&language)?;
+                        Ok(whisper)
+                    });
+                match whisper {
+                    Ok(whisper) => {
+                        app_state.whisper.lock().unwrap().replace(whisper);
+                    }
+                    Err(e) => log::error!("failed to load {}: {}", model_path.display(), e),
                 }
-            };
+            } else if models.remove(&whisper_model).is_some() {
+                store.set("models", serde_json::to_value(&models).unwrap());
+            }
 
-            if let Some(info) = models.get("opus-mt-en-zh.bin") {
+            let pair = app_state.active_pair()?;
+            if let Some(info) = models.get(&pair.model_file()) {
                 let model_path = model_dir.join(&info.file_name);
                 if info.status == "completed" && model_path.exists() {
-                    let (en_token, zh_token) = get_token_path(app.handle());
-                    let translator = Translator::new(
-                        model_path.to_str().unwrap(),
-                        en_token.to_str().unwrap(),
-                        zh_token.to_str().unwrap(),
-                    )?;
-                    app_state.translator.lock().unwrap().replace(translator);
+                    match app_state.create_translator(app.handle(), &pair) {
+                        Ok(translator) => {
+                            app_state.translator.lock().unwrap().replace(translator);
+                        }
+                        Err(e) => log::error!("failed to load {}: {}", pair.id, e),
+                    }
                 } else {
-                    models.remove("opus-mt-en-zh.bin");
+                    models.remove(&pair.model_file());
                     store.set("models", serde_json::to_value(&models).unwrap());
                 }
             };
+            if let Some(settings) = caption_server {
+                let captions = app_state.captions.clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = captions.start(settings).await {
+                        log::error!("failed to start caption server: {}", e);
+                    }
+                });
+            }
             app.manage(app_state);
             Ok(())
         })
@@ -539,10 +1914,44 @@ pub fn run() {
             close_app,
             start_recording,
             stop_recording,
+            set_audio_source,
+            get_language,
+            set_language,
+            export_session,
+            list_sessions,
+            get_session_segments,
+            search_history,
+            delete_session,
+            get_session_recordings,
+            reprocess_session,
+            delete_segment,
+            get_compute_device,
+            pipeline_status,
+            list_audio_devices,
+            select_audio_device,
+            select_mic_device,
+            list_capture_targets,
+            select_capture_target,
+            get_capture_mode,
+            set_capture_mode,
+            get_resample_quality,
+            set_resample_quality,
+            get_recording_format,
+            set_recording_format,
+            set_device_preference,
+            list_translation_pairs,
+            set_translation_pair,
+            list_whisper_models,
+            get_caption_server,
+            set_caption_server,
+            set_whisper_model,
             open_settings,
             open_history,
             close_history,
             download_model,
+            cancel_download,
+            delete_model,
+            import_model,
             show_main_window,
             verify_models
         ])
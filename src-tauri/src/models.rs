@@ -0,0 +1,624 @@
+//! Downloads models into the model directory.
+//!
+//! A download is written to `<file>.part` and resumed with an HTTP Range
+//! request after a failure or cancellation. Once complete it is checked
+//! against its SHA-256 and only then renamed, or converted, into place, so a
+//! model file that exists is always whole. The SHA-256 comes from
+//! `manifest.json` or from the LFS hash Hugging Face reports; a file with
+//! neither is not downloaded at all.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, ETAG, RANGE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// File in the model directory listing the SHA-256 of model files.
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry {
+    file_name: String,
+    sha256: String,
+}
+
+/// Expected checksums of model files, by file name.
+#[derive(Default, Clone, Debug)]
+pub struct Manifest {
+    hashes: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// Reads `manifest.json` in `model_dir`, a list of `{fileName, sha256}`.
+    pub fn load(model_dir: &Path) -> Self {
+        let mut manifest = Self::default();
+        let path = model_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return manifest;
+        }
+        let entries = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str::<Vec<ManifestEntry>>(&s)?));
+        match entries {
+            Ok(entries) => {
+                for entry in entries {
+                    manifest.insert(&entry.file_name, &entry.sha256);
+                }
+            }
+            Err(e) => log::error!("failed to read {}: {}", path.display(), e),
+        }
+        manifest
+    }
+
+    pub fn insert(&mut self, file_name: &str, sha256: &str) {
+        self.hashes
+            .insert(file_name.to_string(), sha256.to_ascii_lowercase());
+    }
+
+    pub fn sha256(&self, file_name: &str) -> Option<&str> {
+        self.hashes.get(file_name).map(String::as_str)
+    }
+}
+
+/// Returned when a download was stopped by `cancel`; the part file is kept.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Download cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+pub fn part_path(dir: &Path, file_name: &str) -> PathBuf {
+    dir.join(format!("{file_name}.part"))
+}
+
+pub struct ModelManager {
+    client: reqwest::Client,
+    /// Doesn't follow redirects, to read the headers of the first response
+    head_client: reqwest::Client,
+    manifest: Manifest,
+    downloads: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl ModelManager {
+    pub fn new(manifest: Manifest) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            head_client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap_or_default(),
+            manifest,
+            downloads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_downloading(&self, file_name: &str) -> bool {
+        self.downloads.lock().unwrap().contains_key(file_name)
+    }
+
+    /// Stops a running download at the next chunk. Returns whether there was one.
+    pub fn cancel(&self, file_name: &str) -> bool {
+        match self.downloads.lock().unwrap().get(file_name) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Downloads `url` to `dir/file_name`, continuing a previous attempt if
+    /// there is one. `on_progress` gets the bytes downloaded and the total,
+    /// which is 0 when the server doesn't say.
+    pub async fn download(
+        &self,
+        url: &str,
+        dir: &Path,
+        file_name: &str,
+        on_progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<()> {
+        self.download_converted(
+            url,
+            dir,
+            file_name,
+            |part, path| Ok(fs::rename(part, path)?),
+            on_progress,
+        )
+        .await
+    }
+
+    /// Like `download`, but once verified the part file is handed to
+    /// `convert` along with the path to write, instead of being renamed. The
+    /// part file is kept when `convert` fails.
+    pub async fn download_converted(
+        &self,
+        url: &str,
+        dir: &Path,
+        file_name: &str,
+        convert: impl FnOnce(&Path, &Path) -> anyhow::Result<()> + Send + 'static,
+        on_progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<()> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let mut downloads = self.downloads.lock().unwrap();
+            if downloads.contains_key(file_name) {
+                anyhow::bail!("{file_name} is already being downloaded");
+            }
+            downloads.insert(file_name.to_string(), cancelled.clone());
+        }
+        let result = self
+            .fetch(url, dir, file_name, &cancelled, convert, on_progress)
+            .await;
+        self.downloads.lock().unwrap().remove(file_name);
+        result
+    }
+
+    async fn fetch(
+        &self,
+        url: &str,
+        dir: &Path,
+        file_name: &str,
+        cancelled: &AtomicBool,
+        convert: impl FnOnce(&Path, &Path) -> anyhow::Result<()> + Send + 'static,
+        mut on_progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<()> {
+        let part = part_path(dir, file_name);
+        let remote = self.remote_file(url).await;
+        // A model that can't be verified is not kept
+        let expected = match self.manifest.sha256(file_name) {
+            Some(sha256) => sha256.to_string(),
+            None => remote
+                .sha256
+                .ok_or_else(|| anyhow::Error::msg(format!("No checksum known for {file_name}")))?,
+        };
+
+        let mut offset = fs::metadata(&part).map_or(0, |m| m.len());
+        let mut request = self.client.get(url);
+        if offset > 0 {
+            log::info!("resuming {} at {} bytes", file_name, offset);
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+        let response = request.send().await?;
+        // The part file already holds the whole model, unless it's longer
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            let size = complete_length(response.headers()).or(remote.size);
+            if size != Some(offset) {
+                fs::remove_file(&part)?;
+                anyhow::bail!(
+                    "{} has {} bytes but {} has {}, discarded it",
+                    part.display(),
+                    offset,
+                    url,
+                    size.map_or("an unknown size".to_string(), |size| size.to_string())
+                );
+            }
+            return finish(part, dir.join(file_name), expected, convert).await;
+        }
+        let response = response.error_for_status()?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            // The server sent everything from the start
+            offset = 0;
+        }
+        let total = response.content_length().map_or(0, |len| offset + len);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&part)?;
+
+        let mut downloaded = offset;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(Cancelled.into());
+            }
+            let chunk = chunk?;
+            file.write_all(&chunk)?;
+            downloaded += chunk.len() as u64;
+            on_progress(downloaded, total);
+        }
+        file.flush()?;
+        drop(file);
+        finish(part, dir.join(file_name), expected, convert).await
+    }
+
+    /// The SHA-256 and size Hugging Face reports for files stored in LFS.
+    async fn remote_file(&self, url: &str) -> RemoteFile {
+        match self.head_client.head(url).send().await {
+            Ok(response) => {
+                let headers = response.headers();
+                RemoteFile {
+                    sha256: sha256_header(headers),
+                    size: headers
+                        .get("x-linked-size")
+                        .or_else(|| {
+                            headers
+                                .get(CONTENT_LENGTH)
+                                .filter(|_| response.status().is_success())
+                        })
+                        .and_then(|size| size.to_str().ok()?.parse().ok()),
+                }
+            }
+            Err(e) => {
+                log::warn!("HEAD {} failed: {}", url, e);
+                RemoteFile::default()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct RemoteFile {
+    sha256: Option<String>,
+    size: Option<u64>,
+}
+
+/// The length in a `Content-Range: bytes */<length>` header, which a 416
+/// response carries.
+fn complete_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit_once('/')?
+        .1
+        .parse()
+        .ok()
+}
+
+fn sha256_header(headers: &HeaderMap) -> Option<String> {
+    ["x-linked-etag", ETAG.as_str()]
+        .into_iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .map(|etag| etag.trim_start_matches("W/").trim_matches('"'))
+        .find(|etag| etag.len() == 64 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+}
+
+/// Checks the downloaded file and moves it into place with `convert`. A file
+/// that doesn't match is deleted, resuming it would only fail again.
+async fn finish(
+    part: PathBuf,
+    path: PathBuf,
+    expected: String,
+    convert: impl FnOnce(&Path, &Path) -> anyhow::Result<()> + Send + 'static,
+) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let actual = sha256_file(&part)?;
+        if actual != expected {
+            fs::remove_file(&part)?;
+            anyhow::bail!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                path.display(),
+                expected,
+                actual
+            );
+        }
+        convert(&part, &path)
+    })
+    .await?
+}
+
+pub fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A minimal HTTP server handing out `body`, with Range support.
+    struct StandIn {
+        url: String,
+        /// Range headers of the GET requests received
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl StandIn {
+        fn start(body: Vec<u8>, delay: Duration) -> Self {
+            Self::serve(body, delay, true)
+        }
+
+        /// Answers HEAD requests without the LFS hash, like for files outside LFS.
+        fn without_checksum(body: Vec<u8>) -> Self {
+            Self::serve(body, Duration::ZERO, false)
+        }
+
+        fn serve(body: Vec<u8>, delay: Duration, checksum: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/model.bin", listener.local_addr().unwrap());
+            let ranges = Arc::new(Mutex::new(Vec::new()));
+            let sha256 = checksum.then(|| hex(&Sha256::digest(&body)));
+            thread::spawn({
+                let ranges = ranges.clone();
+                move || {
+                    for stream in listener.incoming().flatten() {
+                        // The client hangs up on cancelled downloads
+                        let _ = Self::handle(stream, &body, sha256.as_deref(), delay, &ranges);
+                    }
+                }
+            });
+            Self { url, ranges }
+        }
+
+        fn handle(
+            mut stream: TcpStream,
+            body: &[u8],
+            sha256: Option<&str>,
+            delay: Duration,
+            ranges: &Mutex<Vec<Option<String>>>,
+        ) -> std::io::Result<()> {
+            let mut reader = BufReader::new(stream.try_clone()?);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line)?;
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("range") {
+                        range = Some(value.trim().to_string());
+                    }
+                }
+            }
+            let len = body.len();
+            if request_line.starts_with("HEAD") {
+                let etag = sha256.map_or(String::new(), |sha256| {
+                    format!("X-Linked-Etag: \"{sha256}\"\r\n")
+                });
+                return write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {len}\r\n{etag}Connection: close\r\n\r\n"
+                );
+            }
+            ranges.lock().unwrap().push(range.clone());
+            let start = range
+                .as_deref()
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+            let body = match start {
+                Some(start) if start >= len => {
+                    return write!(
+                        stream,
+                        "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n\
+                         Content-Range: bytes */{len}\r\nConnection: close\r\n\r\n"
+                    );
+                }
+                Some(start) => {
+                    write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                         Content-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                        len - start,
+                        start,
+                        len - 1,
+                        len
+                    )?;
+                    &body[start..]
+                }
+                None => {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n"
+                    )?;
+                    body
+                }
+            };
+            for chunk in body.chunks(1024) {
+                stream.write_all(chunk)?;
+                stream.flush()?;
+                thread::sleep(delay);
+            }
+            Ok(())
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn body() -> Vec<u8> {
+        (0..64 * 1024).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("peeches-models-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn downloads_and_verifies_against_the_server_checksum() {
+        let server = StandIn::start(body(), Duration::ZERO);
+        let dir = temp_dir("full");
+        let manager = ModelManager::new(Manifest::default());
+        let mut last = (0, 0);
+        manager
+            .download(&server.url, &dir, "model.bin", |done, total| {
+                last = (done, total)
+            })
+            .await
+            .unwrap();
+        assert_eq!(fs::read(dir.join("model.bin")).unwrap(), body());
+        assert!(!part_path(&dir, "model.bin").exists());
+        assert_eq!(last, (body().len() as u64, body().len() as u64));
+        assert_eq!(*server.ranges.lock().unwrap(), vec![None]);
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_part_file() {
+        let body = body();
+        let server = StandIn::start(body.clone(), Duration::ZERO);
+        let dir = temp_dir("resume");
+        fs::write(part_path(&dir, "model.bin"), &body[..20000]).unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert("model.bin", &hex(&Sha256::digest(&body)));
+        let manager = ModelManager::new(manifest);
+        manager
+            .download(&server.url, &dir, "model.bin", |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(fs::read(dir.join("model.bin")).unwrap(), body);
+        assert_eq!(
+            *server.ranges.lock().unwrap(),
+            vec![Some("bytes=20000-".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn finishes_a_part_file_that_is_already_complete() {
+        let body = body();
+        let server = StandIn::start(body.clone(), Duration::ZERO);
+        let dir = temp_dir("complete");
+        fs::write(part_path(&dir, "model.bin"), &body).unwrap();
+        let manager = ModelManager::new(Manifest::default());
+        manager
+            .download(&server.url, &dir, "model.bin", |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(fs::read(dir.join("model.bin")).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn discards_a_part_file_longer_than_the_model() {
+        let body = body();
+        let server = StandIn::start(body.clone(), Duration::ZERO);
+        let dir = temp_dir("longer");
+        let mut part = body.clone();
+        part.extend_from_slice(b"trailing garbage");
+        fs::write(part_path(&dir, "model.bin"), &part).unwrap();
+        let manager = ModelManager::new(Manifest::default());
+        let err = manager
+            .download(&server.url, &dir, "model.bin", |_, _| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("discarded"), "{err}");
+        assert!(!dir.join("model.bin").exists());
+        assert!(!part_path(&dir, "model.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_model_without_a_checksum() {
+        let server = StandIn::without_checksum(body());
+        let dir = temp_dir("unverified");
+        let manager = ModelManager::new(Manifest::default());
+        let err = manager
+            .download(&server.url, &dir, "model.bin", |_, _| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No checksum"), "{err}");
+        assert!(!dir.join("model.bin").exists());
+        assert!(server.ranges.lock().unwrap().is_empty());
+
+        // A checksum from the manifest is enough
+        let mut manifest = Manifest::default();
+        manifest.insert("model.bin", &hex(&Sha256::digest(body())));
+        let manager = ModelManager::new(manifest);
+        manager
+            .download(&server.url, &dir, "model.bin", |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(fs::read(dir.join("model.bin")).unwrap(), body());
+    }
+
+    #[tokio::test]
+    async fn converts_the_verified_download_into_place() {
+        let server = StandIn::start(body(), Duration::ZERO);
+        let dir = temp_dir("convert");
+        let manager = ModelManager::new(Manifest::default());
+        manager
+            .download_converted(
+                &server.url,
+                &dir,
+                "model.bin",
+                |part, path| {
+                    let mut converted = fs::read(part)?;
+                    converted.reverse();
+                    fs::write(path, converted)?;
+                    Ok(fs::remove_file(part)?)
+                },
+                |_, _| {},
+            )
+            .await
+            .unwrap();
+        let mut expected = body();
+        expected.reverse();
+        assert_eq!(fs::read(dir.join("model.bin")).unwrap(), expected);
+        assert!(!part_path(&dir, "model.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_checksum_mismatch() {
+        let server = StandIn::start(body(), Duration::ZERO);
+        let dir = temp_dir("mismatch");
+        let mut manifest = Manifest::default();
+        manifest.insert("model.bin", &"0".repeat(64));
+        let manager = ModelManager::new(manifest);
+        let err = manager
+            .download(&server.url, &dir, "model.bin", |_, _| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert!(!dir.join("model.bin").exists());
+        assert!(!part_path(&dir, "model.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn cancel_keeps_the_part_file() {
+        let server = StandIn::start(body(), Duration::from_millis(5));
+        let dir = temp_dir("cancel");
+        let manager = ModelManager::new(Manifest::default());
+        let err = manager
+            .download(&server.url, &dir, "model.bin", |done, _| {
+                if done >= 4096 {
+                    assert!(manager.cancel("model.bin"));
+                }
+            })
+            .await
+            .unwrap_err();
+        assert!(err.is::<Cancelled>());
+        assert!(!dir.join("model.bin").exists());
+        assert!(part_path(&dir, "model.bin").exists());
+        assert!(!manager.is_downloading("model.bin"));
+        assert!(!manager.cancel("model.bin"));
+    }
+}
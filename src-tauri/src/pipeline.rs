@@ -0,0 +1,177 @@
+//! Runs the pipeline stages on their own threads and keeps them alive.
+//!
+//! Errors while handling a single item are reported and the item is skipped.
+//! A stage that panics is restarted with a fresh worker, after a short backoff
+//! that grows while it keeps crashing, reading from the same channel.
+
+use std::any::Any;
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+/// A stage that ran this long before crashing is restarted without backoff
+/// from earlier crashes.
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Whisper,
+    Segmenter,
+    Translator,
+    Recorder,
+}
+
+/// Payload of the `pipeline-error` event.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineError {
+    pub stage: Stage,
+    pub message: String,
+    /// The stage crashed and is being restarted, rather than skipping one item
+    pub restarting: bool,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StageStatus {
+    pub stage: Stage,
+    pub running: bool,
+    pub processed: u64,
+    pub errors: u64,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+type Worker<T> = Box<dyn FnMut(T) -> anyhow::Result<()> + Send>;
+
+#[derive(Clone)]
+pub struct Supervisor {
+    app: AppHandle,
+    stages: Arc<Mutex<Vec<StageStatus>>>,
+}
+
+impl Supervisor {
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app,
+            stages: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Runs workers made by `make_worker` on every item from `receiver` until the
+    /// sending side is dropped. `make_worker` is called again as soon as a
+    /// worker panics, and should clear the poison of the locks it shares.
+    pub fn spawn<T, F>(&self, stage: Stage, receiver: mpsc::Receiver<T>, make_worker: F)
+    where
+        T: Send + 'static,
+        F: Fn() -> Worker<T> + Send + 'static,
+    {
+        self.stages.lock().unwrap().push(StageStatus {
+            stage,
+            running: false,
+            processed: 0,
+            errors: 0,
+            restarts: 0,
+            last_error: None,
+        });
+        let receiver = Arc::new(Mutex::new(receiver));
+        let supervisor = self.clone();
+        thread::spawn(move || {
+            let mut restarts = 0;
+            // Crashes since the stage last ran for a while, sets the backoff
+            let mut failures = 0;
+            let mut worker = make_worker();
+            loop {
+                supervisor.update(stage, |s| s.running = true);
+                let started = Instant::now();
+                let result = supervisor.run(stage, receiver.clone(), worker);
+                supervisor.update(stage, |s| s.running = false);
+                let Err(panic) = result else {
+                    return;
+                };
+                // Making the next worker right away clears the locks the
+                // crashed one poisoned, commands using them keep working
+                // during the backoff
+                worker = make_worker();
+                if started.elapsed() >= HEALTHY_RUN {
+                    failures = 0;
+                }
+                failures += 1;
+                restarts += 1;
+                supervisor.update(stage, |s| s.restarts = restarts);
+                supervisor.report(stage, panic_message(&*panic), true);
+                thread::sleep(Duration::from_millis(500 * u64::from(failures.min(10))));
+            }
+        });
+    }
+
+    fn run<T: Send + 'static>(
+        &self,
+        stage: Stage,
+        receiver: Arc<Mutex<mpsc::Receiver<T>>>,
+        mut worker: Worker<T>,
+    ) -> thread::Result<()> {
+        let supervisor = self.clone();
+        thread::Builder::new()
+            .name(format!("{:?}", stage).to_lowercase())
+            .spawn(move || loop {
+                let item = receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+                let Ok(item) = item else {
+                    return;
+                };
+                match worker(item) {
+                    Ok(()) => supervisor.update(stage, |s| s.processed += 1),
+                    Err(e) => supervisor.report(stage, format!("{:#}", e), false),
+                }
+            })
+            .expect("failed to spawn pipeline thread")
+            .join()
+    }
+
+    fn report(&self, stage: Stage, message: String, restarting: bool) {
+        log::error!("{:?} stage failed: {}", stage, message);
+        self.update(stage, |s| {
+            s.errors += 1;
+            s.last_error = Some(message.clone());
+        });
+        let error = PipelineError {
+            stage,
+            message,
+            restarting,
+        };
+        if let Err(e) = self.app.emit("pipeline-error", error) {
+            log::error!("failed to emit pipeline error: {}", e);
+        }
+    }
+
+    fn update(&self, stage: Stage, f: impl FnOnce(&mut StageStatus)) {
+        let mut stages = self.stages.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(status) = stages.iter_mut().find(|s| s.stage == stage) {
+            f(status);
+        }
+    }
+
+    pub fn status(&self) -> Vec<StageStatus> {
+        self.stages
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked".to_string()
+    }
+}
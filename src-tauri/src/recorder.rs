@@ -0,0 +1,426 @@
+//! Keeps the captured audio of each session on disk, one file per track.
+//!
+//! Files start with the first sample captured after `start_recording`, the
+//! same origin segment timestamps are counted from, so a segment's audio is
+//! found at its `start_ms` in the recording of its track.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Track;
+use crate::history::History;
+
+const SAMPLE_RATE: u32 = 16000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// 16-bit PCM, about 1.9MB per minute
+    Wav,
+    /// Ogg Opus tuned for speech, about 0.2MB per minute
+    Opus,
+}
+
+impl RecordingFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Wav => "wav",
+            RecordingFormat::Opus => "opus",
+        }
+    }
+}
+
+/// The audio of one track of a session.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Recording {
+    pub track: Track,
+    pub path: PathBuf,
+}
+
+/// Items of the recorder stage.
+pub enum RecorderEvent {
+    Start {
+        session_id: i64,
+        format: RecordingFormat,
+    },
+    Audio {
+        track: Track,
+        samples: Vec<f32>,
+    },
+    Stop,
+}
+
+/// Decodes a recording back into 16kHz mono samples.
+pub fn read(path: &Path) -> anyhow::Result<Vec<f32>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("opus") => read_opus(path),
+        _ => crate::audio::decode_file(path),
+    }
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    history: Arc<Mutex<History>>,
+    session: Option<(i64, RecordingFormat)>,
+    writers: HashMap<Track, Writer>,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, history: Arc<Mutex<History>>) -> Self {
+        Self {
+            dir,
+            history,
+            session: None,
+            writers: HashMap::new(),
+        }
+    }
+
+    pub fn handle(&mut self, event: RecorderEvent) -> anyhow::Result<()> {
+        match event {
+            RecorderEvent::Start { session_id, format } => {
+                self.finish()?;
+                fs::create_dir_all(&self.dir)?;
+                self.session = Some((session_id, format));
+                Ok(())
+            }
+            RecorderEvent::Audio { track, samples } => {
+                let Some((session_id, format)) = self.session else {
+                    return Ok(());
+                };
+                if !self.writers.contains_key(&track) {
+                    let path = self.dir.join(format!(
+                        "{}-{}.{}",
+                        session_id,
+                        track.as_str(),
+                        format.extension()
+                    ));
+                    let writer = match Writer::create(&path, format) {
+                        Ok(writer) => writer,
+                        Err(e) => {
+                            // Don't report the same failure for every chunk
+                            self.session = None;
+                            return Err(e);
+                        }
+                    };
+                    self.writers.insert(track, writer);
+                    self.history
+                        .lock()
+                        .unwrap()
+                        .add_recording(session_id, &Recording { track, path })?;
+                }
+                self.writers.get_mut(&track).unwrap().write(&samples)
+            }
+            RecorderEvent::Stop => self.finish(),
+        }
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.session = None;
+        for (_, writer) in self.writers.drain() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+enum Writer {
+    Wav(WavWriter),
+    Opus(OpusWriter),
+}
+
+impl Writer {
+    fn create(path: &Path, format: RecordingFormat) -> anyhow::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            RecordingFormat::Wav => Writer::Wav(WavWriter::new(file)?),
+            RecordingFormat::Opus => Writer::Opus(OpusWriter::new(file)?),
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        match self {
+            Writer::Wav(writer) => writer.write(samples),
+            Writer::Opus(writer) => writer.write(samples),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Writer::Wav(writer) => writer.finish(),
+            Writer::Opus(writer) => writer.finish(),
+        }
+    }
+}
+
+struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    fn new(mut file: BufWriter<File>) -> anyhow::Result<Self> {
+        // Sizes are filled in by `finish`
+        file.write_all(&Self::header(0))?;
+        Ok(Self { file, samples: 0 })
+    }
+
+    fn header(samples: u32) -> [u8; 44] {
+        let data_len = samples * 2;
+        let mut header = [0u8; 44];
+        header[0..4].copy_from_slice(b"RIFF");
+        header[4..8].copy_from_slice(&(36 + data_len).to_le_bytes());
+        header[8..16].copy_from_slice(b"WAVEfmt ");
+        header[16..20].copy_from_slice(&16u32.to_le_bytes());
+        // PCM, mono
+        header[20..22].copy_from_slice(&1u16.to_le_bytes());
+        header[22..24].copy_from_slice(&1u16.to_le_bytes());
+        header[24..28].copy_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header[28..32].copy_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+        header[32..34].copy_from_slice(&2u16.to_le_bytes());
+        header[34..36].copy_from_slice(&16u16.to_le_bytes());
+        header[36..40].copy_from_slice(b"data");
+        header[40..44].copy_from_slice(&data_len.to_le_bytes());
+        header
+    }
+
+    fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&Self::header(self.samples))?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// 20ms, the frame size Opus handles best
+const OPUS_FRAME: usize = SAMPLE_RATE as usize / 50;
+/// Samples at 48kHz the decoder drops at the start, libopus' encoder delay
+const PRE_SKIP: u16 = 312;
+/// Each file holds a single logical stream
+const SERIAL: u32 = 1;
+
+// Symphonia has no Opus decoder
+fn read_opus(path: &Path) -> anyhow::Result<Vec<f32>> {
+    let mut packets = ogg::reading::PacketReader::new(BufReader::new(File::open(path)?));
+    let mut decoder = opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono)?;
+    // Room for the longest packet Opus allows, 120ms
+    let mut frame = vec![0f32; SAMPLE_RATE as usize * 120 / 1000];
+    let mut samples = Vec::new();
+    let mut headers = 0;
+    let mut end = None;
+    while let Some(packet) = packets.read_packet()? {
+        // OpusHead and OpusTags
+        if headers < 2 {
+            headers += 1;
+            continue;
+        }
+        let len = decoder.decode_float(&packet.data, &mut frame, false)?;
+        samples.extend_from_slice(&frame[..len]);
+        if packet.last_in_stream() {
+            end = Some(packet.absgp_page());
+        }
+    }
+    // Trim the encoder delay, and the padding of the last frame unless the
+    // recording was cut short
+    let mut samples = samples.split_off((PRE_SKIP as usize / 3).min(samples.len()));
+    if let Some(end) = end {
+        samples.truncate((end.saturating_sub(PRE_SKIP as u64) / 3) as usize);
+    }
+    Ok(samples)
+}
+
+struct OpusWriter {
+    packets: ogg::writing::PacketWriter<'static, BufWriter<File>>,
+    encoder: opus::Encoder,
+    pending: Vec<f32>,
+    /// Samples written by the caller, at 16kHz
+    samples: u64,
+    /// Samples encoded so far, at 48kHz as Ogg Opus counts them
+    granule: u64,
+}
+
+impl OpusWriter {
+    fn new(file: BufWriter<File>) -> anyhow::Result<Self> {
+        use ogg::writing::PacketWriteEndInfo;
+
+        let mut packets = ogg::writing::PacketWriter::new(file);
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(1); // channels
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mapping family
+        packets.write_packet(head, SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = b"peeches";
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no comments
+        packets.write_packet(tags, SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        let encoder =
+            opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Voip)?;
+        Ok(Self {
+            packets,
+            encoder,
+            pending: Vec::new(),
+            samples: 0,
+            granule: 0,
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.pending.extend_from_slice(samples);
+        self.samples += samples.len() as u64;
+        self.encode(false)
+    }
+
+    /// Encodes whole frames of the pending samples. The last frame of the
+    /// stream carries the real length, so padding is trimmed on playback.
+    fn encode(&mut self, end: bool) -> anyhow::Result<()> {
+        use ogg::writing::PacketWriteEndInfo;
+
+        let frames = self.pending.len() / OPUS_FRAME;
+        let end_granule = PRE_SKIP as u64 + self.samples * 3;
+        let mut packet = [0u8; 4000];
+        for (i, frame) in self.pending.chunks_exact(OPUS_FRAME).enumerate() {
+            let len = self.encoder.encode_float(frame, &mut packet)?;
+            self.granule += OPUS_FRAME as u64 * 3;
+            let (info, granule) = if end && i + 1 == frames {
+                (PacketWriteEndInfo::EndStream, end_granule)
+            } else {
+                (
+                    PacketWriteEndInfo::NormalPacket,
+                    self.granule.min(end_granule),
+                )
+            };
+            self.packets
+                .write_packet(packet[..len].to_vec(), SERIAL, info, granule)?;
+        }
+        self.pending.drain(..frames * OPUS_FRAME);
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        // Flush the encoder delay, then pad to a whole frame
+        let padded = (self.pending.len() + PRE_SKIP as usize / 3 + 1).div_ceil(OPUS_FRAME);
+        self.pending.resize(padded * OPUS_FRAME, 0.0);
+        self.encode(true)?;
+        self.packets.into_inner().flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("peeches-recorder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tone(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.5 * (i as f32 / SAMPLE_RATE as f32 * 440.0 * std::f32::consts::TAU).sin())
+            .collect()
+    }
+
+    /// Writes `samples` in chunks of uneven size, the way capture delivers them.
+    fn record(path: &Path, format: RecordingFormat, samples: &[f32]) {
+        let mut writer = Writer::create(path, format).unwrap();
+        for chunk in samples.chunks(1000) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let dir = temp_dir("wav");
+        let path = dir.join("1-me.wav");
+        let samples = tone(SAMPLE_RATE as usize + 123);
+        record(&path, RecordingFormat::Wav, &samples);
+
+        let bytes = fs::read(&path).unwrap();
+        let data_len = samples.len() as u32 * 2;
+        assert_eq!(bytes.len(), 44 + data_len as usize);
+        assert_eq!(bytes[4..8], (36 + data_len).to_le_bytes());
+        assert_eq!(bytes[40..44], data_len.to_le_bytes());
+
+        let decoded = read(&path).unwrap();
+        assert_eq!(decoded.len(), samples.len());
+        for (a, b) in decoded.iter().zip(&samples) {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn opus_round_trip() {
+        let dir = temp_dir("opus");
+        // Less than a frame, whole frames, and a partial last frame
+        for len in [100, OPUS_FRAME * 50, SAMPLE_RATE as usize + 123] {
+            let path = dir.join(format!("{len}.opus"));
+            let samples = tone(len);
+            record(&path, RecordingFormat::Opus, &samples);
+
+            let mut packets =
+                ogg::reading::PacketReader::new(BufReader::new(File::open(&path).unwrap()));
+            let head = packets.read_packet().unwrap().unwrap();
+            assert_eq!(&head.data[..8], b"OpusHead");
+            assert_eq!(head.data[10..12], PRE_SKIP.to_le_bytes());
+            assert_eq!(head.data[12..16], SAMPLE_RATE.to_le_bytes());
+            assert_eq!(head.absgp_page(), 0);
+            let tags = packets.read_packet().unwrap().unwrap();
+            assert_eq!(&tags.data[..8], b"OpusTags");
+            assert_eq!(tags.absgp_page(), 0);
+            let mut granules = vec![];
+            let mut last = None;
+            while let Some(packet) = packets.read_packet().unwrap() {
+                granules.push(packet.absgp_page());
+                last = Some(packet);
+            }
+            assert!(granules.windows(2).all(|pair| pair[0] <= pair[1]));
+            // The last page counts the samples written at 48kHz, after the pre-skip
+            let last = last.unwrap();
+            assert!(last.last_in_stream());
+            assert_eq!(last.absgp_page(), PRE_SKIP as u64 + len as u64 * 3);
+
+            // Decoding trims the pre-skip and the padding of the last frame
+            let decoded = read(&path).unwrap();
+            assert_eq!(decoded.len(), len, "{len} samples");
+            if len >= OPUS_FRAME * 10 {
+                let (expected, actual) = (rms(&samples), rms(&decoded));
+                assert!(
+                    (actual - expected).abs() < expected * 0.2,
+                    "rms {actual} instead of {expected}"
+                );
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,398 @@
+use std::path::{Component, Path, PathBuf};
+
+use candle_transformers::models::marian;
+use serde::{Deserialize, Serialize};
+
+/// The pair used until the user picks another one.
+pub const DEFAULT_PAIR: &str = "opus-mt-en-zh";
+
+/// The whisper model used until the user picks another one.
+pub const DEFAULT_WHISPER_MODEL: &str = "ggml-base-q5_1.bin";
+
+const WHISPER_MODEL_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+/// Marian tokenizers converted by the candle authors, see candle-examples/examples/marian-mt
+const TOKENIZER_URL: &str = "https://huggingface.co/lmz/candle-marian/resolve/main";
+
+/// File in the model directory where users can register extra pairs.
+const USER_PAIRS_FILE: &str = "translation-pairs.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenizerFile {
+    pub file_name: String,
+    /// Where to download the tokenizer from, `None` if it ships with the app
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Sentencepiece model to build the tokenizer from, for models that come
+    /// without a `tokenizer.json`
+    #[serde(default)]
+    pub sentencepiece: Option<SentencePieceFiles>,
+}
+
+impl TokenizerFile {
+    /// Whether the tokenizer ships with the app instead of living in the model directory.
+    pub fn is_bundled(&self) -> bool {
+        self.url.is_none() && self.sentencepiece.is_none()
+    }
+}
+
+/// The files `sentencepiece::convert` turns into a tokenizer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SentencePieceFiles {
+    /// `source.spm` or `target.spm` of the model
+    pub model_url: String,
+    /// `vocab.json` of the model, mapping pieces to token ids
+    pub vocab_url: String,
+}
+
+/// A Marian/OPUS-MT model translating `source` into `target`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslationPair {
+    pub id: String,
+    pub source: String,
+    pub target: String,
+    pub model_url: String,
+    /// `model_url` is a PyTorch checkpoint, converted to safetensors once downloaded
+    #[serde(default)]
+    pub pytorch_weights: bool,
+    pub source_tokenizer: TokenizerFile,
+    pub target_tokenizer: TokenizerFile,
+    /// Hugging Face `config.json`, saved as `<id>.config.json` next to the model
+    #[serde(default)]
+    pub config_url: Option<String>,
+    /// Hugging Face `generation_config.json`, saved as `<id>.generation_config.json`
+    #[serde(default)]
+    pub generation_config_url: Option<String>,
+    /// Used when the model comes without a `config.json`
+    #[serde(default, skip_serializing)]
+    pub config: Option<marian::Config>,
+    /// Where an imported model lives, `None` for models downloaded into the
+    /// model directory
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+impl TranslationPair {
+    /// Name of the safetensors file in the model directory, or the full path
+    /// of an imported model.
+    pub fn model_file(&self) -> String {
+        match &self.path {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => format!("{}.bin", self.id),
+        }
+    }
+
+    /// Extra files downloaded alongside the model, as `(file name, url)`.
+    pub fn config_files(&self) -> Vec<(String, &str)> {
+        let mut files = vec![];
+        if let Some(url) = &self.config_url {
+            files.push((format!("{}.config.json", self.id), url.as_str()));
+        }
+        if let Some(url) = &self.generation_config_url {
+            files.push((format!("{}.generation_config.json", self.id), url.as_str()));
+        }
+        files
+    }
+}
+
+/// A ggml whisper model from the whisper.cpp repository.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WhisperModel {
+    pub file_name: String,
+    /// tiny, base, small, medium, large-v3 or large-v3-turbo
+    pub size: String,
+    /// `None` for the full precision model
+    pub quantization: Option<String>,
+    pub url: String,
+    /// Approximate download size
+    pub size_mb: u32,
+    /// Imported from a local file, `file_name` is then its full path
+    #[serde(default)]
+    pub imported: bool,
+}
+
+pub struct Registry {
+    pairs: Vec<TranslationPair>,
+    whisper_models: Vec<WhisperModel>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            pairs: builtin(),
+            whisper_models: builtin_whisper_models(),
+        }
+    }
+}
+
+impl Registry {
+    /// Built-in pairs plus the ones listed in `translation-pairs.json` in `model_dir`.
+    pub fn load(model_dir: &Path) -> Self {
+        let mut registry = Self::default();
+        let path = model_dir.join(USER_PAIRS_FILE);
+        if !path.exists() {
+            return registry;
+        }
+        let pairs = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str::<Vec<TranslationPair>>(&s)?));
+        match pairs {
+            Ok(pairs) => {
+                for pair in pairs {
+                    registry.add_pair(pair);
+                }
+            }
+            Err(e) => log::error!("failed to read {}: {}", path.display(), e),
+        }
+        registry
+    }
+
+    pub fn pairs(&self) -> &[TranslationPair] {
+        &self.pairs
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TranslationPair> {
+        self.pairs.iter().find(|p| p.id == id)
+    }
+
+    pub fn by_model_file(&self, file_name: &str) -> Option<&TranslationPair> {
+        self.pairs.iter().find(|p| p.model_file() == file_name)
+    }
+
+    pub fn whisper_models(&self) -> &[WhisperModel] {
+        &self.whisper_models
+    }
+
+    pub fn whisper_model(&self, file_name: &str) -> Option<&WhisperModel> {
+        self.whisper_models
+            .iter()
+            .find(|m| m.file_name == file_name)
+    }
+
+    /// Where to download a built-in model from. Only plain file names are
+    /// known, so the name is safe to join to the model directory.
+    pub fn download_url(&self, file_name: &str) -> Option<&str> {
+        if !is_plain_file_name(file_name) {
+            return None;
+        }
+        let pair = self
+            .by_model_file(file_name)
+            .filter(|pair| pair.path.is_none())
+            .map(|pair| pair.model_url.as_str());
+        pair.or_else(|| {
+            self.whisper_model(file_name)
+                .filter(|model| !model.imported)
+                .map(|model| model.url.as_str())
+        })
+    }
+
+    pub fn add_pair(&mut self, pair: TranslationPair) {
+        log::info!("register translation pair: {}", pair.id);
+        self.pairs.retain(|p| p.id != pair.id);
+        self.pairs.push(pair);
+    }
+
+    pub fn add_whisper_model(&mut self, model: WhisperModel) {
+        log::info!("register whisper model: {}", model.file_name);
+        self.whisper_models
+            .retain(|m| m.file_name != model.file_name);
+        self.whisper_models.push(model);
+    }
+
+    /// Forgets an imported model. Returns whether there was one.
+    pub fn remove_imported(&mut self, file_name: &str) -> bool {
+        let len = self.pairs.len() + self.whisper_models.len();
+        self.pairs
+            .retain(|p| p.path.is_none() || p.model_file() != file_name);
+        self.whisper_models
+            .retain(|m| !m.imported || m.file_name != file_name);
+        len != self.pairs.len() + self.whisper_models.len()
+    }
+}
+
+fn is_plain_file_name(file_name: &str) -> bool {
+    let mut components = Path::new(file_name).components();
+    matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !file_name.contains(['/', '\\'])
+}
+
+/// Quantizations published for a whisper size, with approximate sizes in MB.
+type Quantizations = &'static [(Option<&'static str>, u32)];
+
+fn builtin_whisper_models() -> Vec<WhisperModel> {
+    let variants: [(&str, Quantizations); 6] = [
+        (
+            "tiny",
+            &[(None, 75), (Some("q5_1"), 31), (Some("q8_0"), 42)],
+        ),
+        (
+            "base",
+            &[(None, 142), (Some("q5_1"), 57), (Some("q8_0"), 78)],
+        ),
+        (
+            "small",
+            &[(None, 466), (Some("q5_1"), 181), (Some("q8_0"), 252)],
+        ),
+        (
+            "medium",
+            &[(None, 1500), (Some("q5_0"), 514), (Some("q8_0"), 785)],
+        ),
+        ("large-v3", &[(None, 2900), (Some("q5_0"), 1080)]),
+        (
+            "large-v3-turbo",
+            &[(None, 1500), (Some("q5_0"), 547), (Some("q8_0"), 834)],
+        ),
+    ];
+    variants
+        .into_iter()
+        .flat_map(|(size, quantizations)| {
+            quantizations.iter().map(move |&(quantization, size_mb)| {
+                let file_name = match quantization {
+                    Some(q) => format!("ggml-{size}-{q}.bin"),
+                    None => format!("ggml-{size}.bin"),
+                };
+                WhisperModel {
+                    url: format!("{WHISPER_MODEL_URL}/{file_name}"),
+                    file_name,
+                    size: size.to_string(),
+                    quantization: quantization.map(str::to_string),
+                    size_mb,
+                    imported: false,
+                }
+            })
+        })
+        .collect()
+}
+
+fn builtin() -> Vec<TranslationPair> {
+    vec![
+        TranslationPair {
+            id: "opus-mt-en-zh".to_string(),
+            source: "en".to_string(),
+            target: "zh".to_string(),
+            model_url: "https://huggingface.co/Helsinki-NLP/opus-mt-en-zh/resolve/refs%2Fpr%2F26/model.safetensors".to_string(),
+            pytorch_weights: false,
+            source_tokenizer: TokenizerFile {
+                file_name: "tokenizer-marian-base-en.json".to_string(),
+                url: None,
+                sentencepiece: None,
+            },
+            target_tokenizer: TokenizerFile {
+                file_name: "tokenizer-marian-base-zh.json".to_string(),
+                url: None,
+                sentencepiece: None,
+            },
+            config_url: None,
+            generation_config_url: None,
+            path: None,
+            // https://huggingface.co/Helsinki-NLP/opus-mt-en-zh/blob/main/config.json
+            config: Some(marian::Config {
+                vocab_size: 65001,
+                decoder_vocab_size: Some(65001),
+                max_position_embeddings: 512,
+                encoder_layers: 6,
+                encoder_ffn_dim: 2048,
+                encoder_attention_heads: 8,
+                decoder_layers: 6,
+                decoder_ffn_dim: 2048,
+                decoder_attention_heads: 8,
+                use_cache: true,
+                is_encoder_decoder: true,
+                activation_function: candle_nn::Activation::Swish,
+                d_model: 512,
+                decoder_start_token_id: 65000,
+                scale_embedding: true,
+                pad_token_id: 65000,
+                eos_token_id: 0,
+                forced_eos_token_id: 0,
+                share_encoder_decoder_embeddings: true,
+            }),
+        },
+        TranslationPair {
+            id: "opus-mt-fr-en".to_string(),
+            source: "fr".to_string(),
+            target: "en".to_string(),
+            model_url: "https://huggingface.co/Helsinki-NLP/opus-mt-fr-en/resolve/refs%2Fpr%2F4/model.safetensors".to_string(),
+            pytorch_weights: false,
+            source_tokenizer: TokenizerFile {
+                file_name: "tokenizer-opus-mt-fr-en-fr.json".to_string(),
+                url: Some(format!("{TOKENIZER_URL}/tokenizer-marian-base-fr.json")),
+                sentencepiece: None,
+            },
+            target_tokenizer: TokenizerFile {
+                file_name: "tokenizer-opus-mt-fr-en-en.json".to_string(),
+                url: Some(format!("{TOKENIZER_URL}/tokenizer-marian-base-en.json")),
+                sentencepiece: None,
+            },
+            config_url: None,
+            generation_config_url: None,
+            config: Some(marian::Config::opus_mt_fr_en()),
+            path: None,
+        },
+        helsinki_pair("opus-mt-en-de", "en", "de"),
+        // Trained on Tatoeba-Challenge data, opus-mt-en-jap only saw Bible translations
+        helsinki_pair("opus-tatoeba-en-ja", "en", "ja"),
+        helsinki_pair("opus-mt-zh-en", "zh", "en"),
+    ]
+}
+
+/// A model as the Helsinki-NLP repository publishes it: a PyTorch checkpoint,
+/// sentencepiece models shared with a `vocab.json`, and its `config.json`.
+fn helsinki_pair(id: &str, source: &str, target: &str) -> TranslationPair {
+    let repo = format!("https://huggingface.co/Helsinki-NLP/{id}/resolve/main");
+    let tokenizer = |language: &str, spm: &str| TokenizerFile {
+        file_name: format!("tokenizer-{id}-{language}.json"),
+        url: None,
+        sentencepiece: Some(SentencePieceFiles {
+            model_url: format!("{repo}/{spm}"),
+            vocab_url: format!("{repo}/vocab.json"),
+        }),
+    };
+    TranslationPair {
+        id: id.to_string(),
+        source: source.to_string(),
+        target: target.to_string(),
+        model_url: format!("{repo}/pytorch_model.bin"),
+        pytorch_weights: true,
+        source_tokenizer: tokenizer(source, "source.spm"),
+        target_tokenizer: tokenizer(target, "target.spm"),
+        config_url: Some(format!("{repo}/config.json")),
+        generation_config_url: None,
+        config: None,
+        path: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_built_in_file_names_can_be_downloaded() {
+        let mut registry = Registry::default();
+        assert!(registry.download_url("ggml-base-q5_1.bin").is_some());
+        assert!(registry.download_url("opus-mt-en-zh.bin").is_some());
+        assert!(registry.download_url("model.bin").is_none());
+        for file_name in ["../ggml-base-q5_1.bin", "/ggml-base-q5_1.bin", "..", ""] {
+            assert!(registry.download_url(file_name).is_none(), "{file_name}");
+        }
+
+        // Pairs from translation-pairs.json are not trusted to be plain
+        registry.add_pair(TranslationPair {
+            id: "../../outside".to_string(),
+            ..helsinki_pair("opus-mt-en-de", "en", "de")
+        });
+        assert!(registry.download_url("../../outside.bin").is_none());
+        registry.add_pair(TranslationPair {
+            id: "imported".to_string(),
+            path: Some(PathBuf::from("/models/imported")),
+            ..helsinki_pair("opus-mt-en-de", "en", "de")
+        });
+        assert!(registry.download_url("/models/imported").is_none());
+    }
+}
@@ -0,0 +1,66 @@
+//! Transcribes the recorded audio of a past session again, e.g. with a larger
+//! whisper model than the one that ran live.
+
+use crate::audio;
+use crate::recorder::{self, Recording};
+use crate::segmenter::Segmenter;
+use crate::session::{Segment, Session};
+use crate::stabilizer::Hypothesis;
+use crate::translate::Translator;
+use crate::whisper::Whisper;
+
+/// Runs every track of a session through whisper, the segmenter and the
+/// translator, reporting progress in percent after each utterance.
+pub fn reprocess(
+    recordings: &[Recording],
+    whisper: &mut Whisper,
+    translator: &mut Translator,
+    mut on_progress: impl FnMut(f32),
+) -> anyhow::Result<Vec<Segment>> {
+    // Also clips the segments of a track the same way a live session does
+    let mut session = Session::default();
+    for (i, recording) in recordings.iter().enumerate() {
+        log::info!("reprocessing {:?}", recording.path);
+        let samples = recorder::read(&recording.path)?;
+        let windows = audio::split_utterances(&samples, recording.track);
+        drop(samples);
+        let count = windows.len();
+        let mut segmenter = Segmenter::default();
+        for (j, window) in windows.into_iter().enumerate() {
+            let window_start_ms = window.start as u64 * 1000 / 16000;
+            let window_end_ms = window.end as u64 * 1000 / 16000;
+            let mut transcript = whisper.transcribe(window.samples)?;
+            transcript.segments.retain(|s| !s.is_likely_silence());
+            if let Some(text) = transcript.speech_text() {
+                let (start_ms, end_ms) = transcript.speech_span(window_end_ms - window_start_ms);
+                // Each utterance is final already, it only needs cutting into sentences
+                let hypothesis = Hypothesis {
+                    segment_id: j as u64,
+                    committed: text,
+                    tentative: String::new(),
+                };
+                let sentences = segmenter.push(
+                    &hypothesis,
+                    &transcript.timings(window_start_ms),
+                    (window_start_ms + start_ms, window_start_ms + end_ms),
+                    true,
+                );
+                for sentence in sentences {
+                    let translated_text = translator.translate(&sentence.text)?;
+                    session.push(Segment {
+                        track: window.track,
+                        start_ms: sentence.start_ms,
+                        end_ms: sentence.end_ms,
+                        original_text: sentence.text,
+                        translated_text,
+                    });
+                }
+            }
+            let done = i as f32 + (j + 1) as f32 / count as f32;
+            on_progress(done / recordings.len() as f32 * 100.0);
+        }
+    }
+    let mut segments = session.segments().to_vec();
+    segments.sort_by_key(|segment| segment.start_ms);
+    Ok(segments)
+}
@@ -0,0 +1,468 @@
+//! Cuts the committed text of an utterance into sentences, so the translator
+//! only ever sees whole ones.
+//!
+//! A sentence ends at terminal punctuation, or where whisper's timestamps
+//! show a pause in the speech, and at the end of the utterance. Until then it
+//! is reported as in progress under the id it will be completed with.
+
+use std::ops::Range;
+
+use crate::stabilizer::{self, Hypothesis};
+
+/// Silence between two whisper segments that ends a sentence
+const PAUSE_MS: u64 = 800;
+
+/// Words whose period doesn't end a sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "st", "vs", "etc", "e.g", "i.e", "inc", "ltd", "no",
+];
+
+/// Where whisper placed a piece of the text.
+#[derive(Clone, Debug)]
+pub struct Timing {
+    /// Byte offset in the text where the piece ends
+    pub end: usize,
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sentence {
+    /// Counted per track, the same while in progress and once complete
+    pub sentence_id: u64,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// All of a complete sentence, the committed part of one in progress
+    pub text: String,
+    /// What may still change of a sentence in progress
+    pub tentative: String,
+    pub complete: bool,
+}
+
+impl Sentence {
+    pub fn original_text(&self) -> String {
+        format!("{}{}", self.text, self.tentative)
+    }
+}
+
+#[derive(Default)]
+pub struct Segmenter {
+    sentence_id: u64,
+    /// Utterance the committed text belongs to
+    segment_id: Option<u64>,
+    /// Bytes of the committed text already sent as complete sentences
+    emitted: usize,
+    last_end_ms: u64,
+}
+
+impl Segmenter {
+    /// Takes the next hypothesis of an utterance spanning `span` ms, with
+    /// `timings` relative to its text. Returns the sentences it completes,
+    /// followed by the one still in progress if there is one.
+    pub fn push(
+        &mut self,
+        hypothesis: &Hypothesis,
+        timings: &[Timing],
+        span: (u64, u64),
+        is_final: bool,
+    ) -> Vec<Sentence> {
+        if self.segment_id != Some(hypothesis.segment_id) {
+            self.segment_id = Some(hypothesis.segment_id);
+            self.emitted = 0;
+        }
+        let committed = hypothesis.committed.as_str();
+        let len = committed.len() + hypothesis.tentative.len();
+        // Whisper may re-punctuate the committed text, it can even get shorter
+        let mut start = floor_char_boundary(committed, self.emitted);
+
+        let mut sentences = Vec::new();
+        for end in boundaries(committed, &hypothesis.tentative, timings, start) {
+            sentences.extend(self.complete(committed, start..end, timings, span, len));
+            start = end;
+        }
+        if is_final {
+            let rest = start..committed.len();
+            sentences.extend(self.complete(committed, rest, timings, span, len));
+            self.segment_id = None;
+            self.emitted = 0;
+            return sentences;
+        }
+        self.emitted = start;
+
+        let text = committed[start..].trim_start();
+        let tentative = if text.is_empty() {
+            hypothesis.tentative.trim_start()
+        } else {
+            &hypothesis.tentative
+        };
+        if !text.is_empty() || !tentative.trim().is_empty() {
+            sentences.push(Sentence {
+                sentence_id: self.sentence_id,
+                start_ms: time_at(timings, start, true, span, len).max(self.last_end_ms),
+                end_ms: span.1,
+                text: text.to_string(),
+                tentative: tentative.to_string(),
+                complete: false,
+            });
+        }
+        sentences
+    }
+
+    fn complete(
+        &mut self,
+        committed: &str,
+        range: Range<usize>,
+        timings: &[Timing],
+        span: (u64, u64),
+        len: usize,
+    ) -> Option<Sentence> {
+        let text = committed[range.clone()].trim();
+        if text.is_empty() {
+            return None;
+        }
+        let start_ms = time_at(timings, range.start, true, span, len).max(self.last_end_ms);
+        // At least a moment long, so it isn't dropped as empty
+        let end_ms = time_at(timings, range.end, false, span, len).max(start_ms + 1);
+        let sentence = Sentence {
+            sentence_id: self.sentence_id,
+            start_ms,
+            end_ms,
+            text: text.to_string(),
+            tentative: String::new(),
+            complete: true,
+        };
+        self.sentence_id += 1;
+        self.last_end_ms = end_ms;
+        Some(sentence)
+    }
+
+    /// Starts over for a new recording, whose timestamps count from zero.
+    /// Sentence ids keep counting so they stay unique.
+    pub fn reset(&mut self) {
+        *self = Self {
+            sentence_id: self.sentence_id,
+            ..Self::default()
+        };
+    }
+}
+
+/// Moves `timings`, given for `raw` as whisper transcribed it, onto `text`,
+/// the stabilized hypothesis made from it. The two differ where the
+/// stabilizer kept committed words whisper changed, so a piece end is carried
+/// over through the words both share at their start or end. Pieces ending
+/// where they differ are dropped, they could cut a word in `text`.
+pub fn align_timings(timings: &[Timing], raw: &str, text: &str) -> Vec<Timing> {
+    if raw == text {
+        return timings.to_vec();
+    }
+    let raw_words = stabilizer::split_words(raw);
+    let words = stabilizer::split_words(text);
+    let same = |(a, b): &(&String, &String)| a.trim() == b.trim();
+    let prefix = raw_words.iter().zip(&words).take_while(same).count();
+    let suffix = raw_words
+        .iter()
+        .rev()
+        .zip(words.iter().rev())
+        .take(raw_words.len().min(words.len()) - prefix)
+        .take_while(same)
+        .count();
+    let word_ends = |words: &[String]| -> Vec<usize> {
+        words
+            .iter()
+            .scan(0, |end, word| {
+                *end += word.len();
+                Some(*end)
+            })
+            .collect()
+    };
+    let raw_ends = word_ends(&raw_words);
+    let ends = word_ends(&words);
+    timings
+        .iter()
+        .filter_map(|timing| {
+            // The word the piece ends with, trailing whitespace aside
+            let i = raw_ends.iter().rposition(|&end| end <= timing.end)?;
+            if !raw.get(raw_ends[i]..timing.end)?.trim().is_empty() {
+                return None;
+            }
+            let end = if i < prefix {
+                ends[i]
+            } else if raw_words.len() - i <= suffix {
+                ends[words.len() - (raw_words.len() - i)]
+            } else {
+                return None;
+            };
+            Some(Timing {
+                end,
+                ..timing.clone()
+            })
+        })
+        .collect()
+}
+
+/// Offsets in `committed` after `from` where a sentence ends.
+fn boundaries(committed: &str, tentative: &str, timings: &[Timing], from: usize) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut chars = committed[from..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if !is_terminal(c) {
+            continue;
+        }
+        // Keep closing quotes and brackets with their sentence
+        let mut end = from + i + c.len_utf8();
+        while let Some(&(j, next)) = chars.peek() {
+            if !is_closing(next) {
+                break;
+            }
+            end = from + j + next.len_utf8();
+            chars.next();
+        }
+        let next = committed[end..].chars().next();
+        let confirmed = match next {
+            Some(next) => next.is_whitespace() || is_wide_terminal(c),
+            // A period at the end of the committed text may still turn out to
+            // be a decimal point
+            None => is_wide_terminal(c) || tentative.starts_with(char::is_whitespace),
+        };
+        if confirmed && !(c == '.' && is_abbreviation(&committed[..end - 1])) {
+            ends.push(end);
+        }
+    }
+    for pair in timings.windows(2) {
+        let end = pair[0].end;
+        let paused = pair[1].start_ms.saturating_sub(pair[0].end_ms) >= PAUSE_MS;
+        if paused && end > from && end <= committed.len() && committed.is_char_boundary(end) {
+            ends.push(end);
+        }
+    }
+    ends.sort_unstable();
+    ends.dedup();
+    ends
+}
+
+/// Time at `offset` in a text `len` bytes long, interpolated within the
+/// piece holding it, or within `span` without timings. An offset between two
+/// pieces is the start of the next one for a sentence `starting` there.
+fn time_at(timings: &[Timing], offset: usize, starting: bool, span: (u64, u64), len: usize) -> u64 {
+    let whole = [Timing {
+        end: len,
+        start_ms: span.0,
+        end_ms: span.1,
+    }];
+    let timings = if timings.is_empty() {
+        &whole[..]
+    } else {
+        timings
+    };
+    let mut piece_start = 0;
+    for piece in timings {
+        if offset < piece.end || (offset == piece.end && !starting) {
+            let piece_len = piece.end.saturating_sub(piece_start).max(1) as u64;
+            let at = offset.saturating_sub(piece_start) as u64;
+            let duration = piece.end_ms.saturating_sub(piece.start_ms);
+            return piece.start_ms + duration * at.min(piece_len) / piece_len;
+        }
+        piece_start = piece.end;
+    }
+    timings.last().map_or(span.1, |piece| piece.end_ms)
+}
+
+fn is_terminal(c: char) -> bool {
+    matches!(c, '.' | '?' | '!' | '…') || is_wide_terminal(c)
+}
+
+/// CJK punctuation, which is never followed by a space.
+fn is_wide_terminal(c: char) -> bool {
+    matches!(c, '。' | '？' | '！' | '．')
+}
+
+fn is_closing(c: char) -> bool {
+    matches!(c, '"' | '\'' | ')' | ']' | '”' | '’' | '）' | '」' | '』')
+}
+
+/// Whether the text before a period ends in an abbreviation or an initial.
+fn is_abbreviation(before: &str) -> bool {
+    let word = before
+        .rsplit(|c: char| c.is_whitespace() || c == '(' || c == '"')
+        .next()
+        .unwrap_or_default();
+    let single_initial = word.chars().count() == 1 && word.chars().all(char::is_uppercase);
+    single_initial
+        || ABBREVIATIONS
+            .iter()
+            .any(|abbreviation| word.eq_ignore_ascii_case(abbreviation))
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hypothesis(segment_id: u64, committed: &str, tentative: &str) -> Hypothesis {
+        Hypothesis {
+            segment_id,
+            committed: committed.to_string(),
+            tentative: tentative.to_string(),
+        }
+    }
+
+    #[test]
+    fn splits_committed_text_into_sentences() {
+        let mut segmenter = Segmenter::default();
+        let sentences = segmenter.push(
+            &hypothesis(0, " Hello there. How are you? I am", " fine"),
+            &[],
+            (0, 3000),
+            false,
+        );
+        let texts: Vec<_> = sentences.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["Hello there.", "How are you?", "I am"]);
+        assert!(sentences[0].complete && sentences[1].complete);
+        assert!(!sentences[2].complete);
+        assert_eq!(sentences[2].tentative, " fine");
+        assert_eq!(
+            sentences.iter().map(|s| s.sentence_id).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn keeps_abbreviations_and_decimals_in_the_sentence() {
+        let mut segmenter = Segmenter::default();
+        let sentences = segmenter.push(
+            &hypothesis(0, " Dr. Smith paid 3.", "5"),
+            &[],
+            (0, 2000),
+            false,
+        );
+        assert_eq!(sentences.len(), 1);
+        assert_eq!(sentences[0].text, "Dr. Smith paid 3.");
+        assert!(!sentences[0].complete);
+    }
+
+    #[test]
+    fn splits_cjk_and_pauses() {
+        let mut segmenter = Segmenter::default();
+        let sentences = segmenter.push(&hypothesis(0, "你好。谢谢", ""), &[], (0, 1000), false);
+        assert_eq!(sentences[0].text, "你好。");
+        assert!(sentences[0].complete);
+
+        let mut segmenter = Segmenter::default();
+        let timings = [
+            Timing {
+                end: 6,
+                start_ms: 0,
+                end_ms: 500,
+            },
+            Timing {
+                end: 12,
+                start_ms: 2000,
+                end_ms: 2500,
+            },
+        ];
+        let sentences = segmenter.push(
+            &hypothesis(0, " hello world", ""),
+            &timings,
+            (0, 2500),
+            false,
+        );
+        assert_eq!(sentences[0].text, "hello");
+        assert!(sentences[0].complete);
+        assert_eq!((sentences[0].start_ms, sentences[0].end_ms), (0, 500));
+        assert_eq!(sentences[1].start_ms, 2000);
+    }
+
+    #[test]
+    fn carries_timings_over_to_the_stabilized_text() {
+        let timing = |end, start_ms, end_ms| Timing {
+            end,
+            start_ms,
+            end_ms,
+        };
+        // Whisper dropped " wanted", which the stabilizer had committed
+        let raw = " I go home";
+        let text = " I wanted go home";
+        let timings = [
+            timing(2, 0, 300),
+            timing(5, 300, 500),
+            timing(10, 1500, 2000),
+        ];
+        let aligned = align_timings(&timings, raw, text);
+        let ends: Vec<usize> = aligned.iter().map(|t| t.end).collect();
+        assert_eq!(ends, [2, 12, 17]);
+        assert_eq!(aligned[1].end_ms, 500);
+
+        // The pause after " go" ends the sentence after the word, not in
+        // the middle of " wanted" at the raw offset
+        let mut segmenter = Segmenter::default();
+        let sentences = segmenter.push(
+            &hypothesis(0, " I wanted go", " home"),
+            &aligned,
+            (0, 2000),
+            false,
+        );
+        assert_eq!(sentences[0].text, "I wanted go");
+        assert!(sentences[0].complete);
+
+        // A piece ending inside a word can't be carried over
+        assert!(align_timings(&[timing(4, 0, 300)], raw, text).is_empty());
+        assert_eq!(align_timings(&timings, raw, raw).len(), 3);
+    }
+
+    #[test]
+    fn sentence_ids_stay_while_in_progress() {
+        let mut segmenter = Segmenter::default();
+        let first = segmenter.push(&hypothesis(0, " Good", " morning"), &[], (0, 1000), false);
+        let second = segmenter.push(
+            &hypothesis(0, " Good morning", " to"),
+            &[],
+            (0, 1500),
+            false,
+        );
+        assert_eq!(first[0].sentence_id, second[0].sentence_id);
+        assert!(!second[0].complete);
+    }
+
+    #[test]
+    fn flushes_the_rest_when_final() {
+        let mut segmenter = Segmenter::default();
+        segmenter.push(&hypothesis(0, " One. Two", ""), &[], (0, 1000), false);
+        let sentences = segmenter.push(&hypothesis(0, " One. Two three", ""), &[], (0, 2000), true);
+        assert_eq!(sentences.len(), 1);
+        assert_eq!(sentences[0].text, "Two three");
+        assert!(sentences[0].complete);
+        assert_eq!(sentences[0].end_ms, 2000);
+
+        // The next utterance starts from its beginning
+        let sentences = segmenter.push(&hypothesis(1, " Four.", ""), &[], (3000, 4000), true);
+        assert_eq!(sentences[0].text, "Four.");
+        assert_eq!(sentences[0].sentence_id, 2);
+    }
+
+    #[test]
+    fn timestamps_restart_after_reset() {
+        let mut segmenter = Segmenter::default();
+        let first = segmenter.push(&hypothesis(0, " First.", ""), &[], (5000, 9000), true);
+        assert_eq!((first[0].start_ms, first[0].end_ms), (5000, 9000));
+
+        // Without a reset the next recording is clamped behind the last one
+        let late = segmenter.push(&hypothesis(0, " Again.", ""), &[], (1000, 2000), true);
+        assert_eq!(late[0].start_ms, 9000);
+
+        segmenter.reset();
+        let second = segmenter.push(&hypothesis(0, " Second.", ""), &[], (1000, 2000), true);
+        assert_eq!((second[0].start_ms, second[0].end_ms), (1000, 2000));
+        assert!(second[0].sentence_id > late[0].sentence_id);
+
+        let third = segmenter.push(&hypothesis(1, " Third.", ""), &[], (1500, 3000), true);
+        assert!(third[0].start_ms >= second[0].end_ms);
+        assert!(third[0].end_ms > third[0].start_ms);
+    }
+}
@@ -0,0 +1,341 @@
+//! Builds `tokenizer.json` files from the sentencepiece models Helsinki-NLP
+//! publishes, the way candle's `convert_slow_tokenizer.py` does for Marian.
+//!
+//! The Marian models only come with `source.spm`/`target.spm` and a
+//! `vocab.json` mapping pieces to the ids the model was trained with, while
+//! the `tokenizers` crate needs a Unigram model indexed by those ids.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+/// `SentencePiece.Type` values that are not ordinary pieces.
+const UNKNOWN: u64 = 2;
+const CONTROL: u64 = 3;
+
+/// Stands in for ids of the shared vocabulary this sentencepiece model can't produce.
+const MISSING_PIECE: &str = "<NIL>";
+const MISSING_SCORE: f64 = -100.0;
+
+struct Piece {
+    piece: String,
+    score: f32,
+    kind: u64,
+}
+
+/// What the conversion needs from a `ModelProto`.
+struct Model {
+    pieces: Vec<Piece>,
+    precompiled_charsmap: Vec<u8>,
+}
+
+/// Converts a serialized sentencepiece `ModelProto` and the `vocab.json` of
+/// the Marian model into the contents of a `tokenizer.json`.
+pub fn convert(spm: &[u8], vocab: &HashMap<String, u32>) -> anyhow::Result<String> {
+    let model = parse_model(spm)?;
+    let len = vocab.values().max().map_or(0, |&id| id as usize + 1);
+    let mut pieces = vec![(MISSING_PIECE.to_string(), MISSING_SCORE); len];
+    let mut unk_id = None;
+    let mut special = vec![];
+    let mut min_score = f64::MAX;
+    for piece in &model.pieces {
+        let Some(&id) = vocab.get(&piece.piece) else {
+            continue;
+        };
+        let score = f64::from(piece.score);
+        pieces[id as usize] = (piece.piece.clone(), score);
+        min_score = min_score.min(score);
+        match piece.kind {
+            UNKNOWN => unk_id = Some(id),
+            CONTROL => special.push((id, piece.piece.clone())),
+            _ => {}
+        }
+    }
+    let unk_id = unk_id.ok_or_else(|| anyhow::Error::msg("sentencepiece model has no <unk>"))?;
+    // sentencepiece scores unknown pieces below every known one
+    pieces[unk_id as usize].1 = min_score - 10.0;
+    // The pad token is in vocab.json only
+    if let Some(&id) = vocab.get("<pad>") {
+        pieces[id as usize] = ("<pad>".to_string(), 0.0);
+        special.push((id, "<pad>".to_string()));
+    }
+    special.push((unk_id, pieces[unk_id as usize].0.clone()));
+    special.sort();
+    special.dedup();
+
+    let added_tokens: Vec<Value> = special
+        .into_iter()
+        .map(|(id, content)| {
+            json!({
+                "id": id,
+                "content": content,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            })
+        })
+        .collect();
+    let mut normalizers = vec![];
+    if !model.precompiled_charsmap.is_empty() {
+        normalizers.push(json!({
+            "type": "Precompiled",
+            "precompiled_charsmap": base64(&model.precompiled_charsmap),
+        }));
+    }
+    normalizers.push(json!({
+        "type": "Replace",
+        "pattern": { "Regex": " {2,}" },
+        "content": " ",
+    }));
+    let metaspace = json!({
+        "type": "Metaspace",
+        "replacement": "\u{2581}",
+        "prepend_scheme": "always",
+        "split": true,
+    });
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": { "type": "Sequence", "normalizers": normalizers },
+        "pre_tokenizer": metaspace,
+        // The translator appends </s> itself
+        "post_processor": null,
+        "decoder": metaspace,
+        "model": {
+            "type": "Unigram",
+            "unk_id": unk_id,
+            "vocab": pieces,
+            "byte_fallback": false,
+        },
+    });
+    Ok(serde_json::to_string(&tokenizer)?)
+}
+
+/// Reads the pieces (field 1) and the normalizer's precompiled charsmap
+/// (field 3, its field 2) of a `ModelProto`.
+fn parse_model(data: &[u8]) -> anyhow::Result<Model> {
+    let mut model = Model {
+        pieces: vec![],
+        precompiled_charsmap: vec![],
+    };
+    let mut fields = Fields(data);
+    while let Some((number, value)) = fields.next_field()? {
+        match (number, value) {
+            (1, Field::Bytes(bytes)) => model.pieces.push(parse_piece(bytes)?),
+            (3, Field::Bytes(bytes)) => {
+                let mut normalizer = Fields(bytes);
+                while let Some((number, value)) = normalizer.next_field()? {
+                    if let (2, Field::Bytes(charsmap)) = (number, value) {
+                        model.precompiled_charsmap = charsmap.to_vec();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if model.pieces.is_empty() {
+        anyhow::bail!("not a sentencepiece model");
+    }
+    Ok(model)
+}
+
+fn parse_piece(data: &[u8]) -> anyhow::Result<Piece> {
+    // Type defaults to NORMAL (1) when left out
+    let mut piece = Piece {
+        piece: String::new(),
+        score: 0.0,
+        kind: 1,
+    };
+    let mut fields = Fields(data);
+    while let Some((number, value)) = fields.next_field()? {
+        match (number, value) {
+            (1, Field::Bytes(bytes)) => piece.piece = String::from_utf8(bytes.to_vec())?,
+            (2, Field::Fixed32(bits)) => piece.score = f32::from_bits(bits),
+            (3, Field::Varint(kind)) => piece.kind = kind,
+            _ => {}
+        }
+    }
+    Ok(piece)
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// The fields of a protobuf message, just enough wire format for sentencepiece models.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn next_field(&mut self) -> anyhow::Result<Option<(u64, Field<'a>)>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Field::Fixed64
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            5 => {
+                let bytes = self.take(4)?;
+                Field::Fixed32(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            wire_type => anyhow::bail!("unsupported protobuf wire type {wire_type}"),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.take(1)?.first().unwrap_or(&0);
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("protobuf varint too long")
+    }
+
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if len > self.0.len() {
+            anyhow::bail!("truncated sentencepiece model");
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes_field(number: u64, bytes: &[u8]) -> Vec<u8> {
+        let mut out = vec![(number << 3 | 2) as u8, bytes.len() as u8];
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    fn piece(text: &str, score: f32, kind: Option<u8>) -> Vec<u8> {
+        let mut out = bytes_field(1, text.as_bytes());
+        out.push(2 << 3 | 5);
+        out.extend_from_slice(&score.to_le_bytes());
+        if let Some(kind) = kind {
+            out.extend_from_slice(&[3 << 3, kind]);
+        }
+        out
+    }
+
+    fn spm() -> Vec<u8> {
+        let mut model = vec![];
+        for p in [
+            piece("<unk>", 0.0, Some(2)),
+            piece("<s>", 0.0, Some(3)),
+            piece("</s>", 0.0, Some(3)),
+            piece("\u{2581}hello", -1.5, None),
+            piece("\u{2581}world", -2.5, None),
+            piece("only-in-spm", -3.0, None),
+        ] {
+            model.extend(bytes_field(1, &p));
+        }
+        // normalizer_spec { name: "nmt_nfkc", precompiled_charsmap: "abcd" }
+        let mut normalizer = bytes_field(1, b"nmt_nfkc");
+        normalizer.extend(bytes_field(2, b"abcd"));
+        model.extend(bytes_field(3, &normalizer));
+        model
+    }
+
+    fn vocab() -> HashMap<String, u32> {
+        [
+            ("</s>", 0),
+            ("<unk>", 1),
+            ("\u{2581}hello", 2),
+            ("\u{2581}other", 3),
+            ("\u{2581}world", 4),
+            ("<pad>", 5),
+        ]
+        .into_iter()
+        .map(|(piece, id)| (piece.to_string(), id))
+        .collect()
+    }
+
+    #[test]
+    fn indexes_pieces_by_the_marian_vocabulary() {
+        let tokenizer: Value = serde_json::from_str(&convert(&spm(), &vocab()).unwrap()).unwrap();
+        let model = &tokenizer["model"];
+        assert_eq!(model["type"], "Unigram");
+        assert_eq!(model["unk_id"], 1);
+        assert_eq!(
+            model["vocab"],
+            json!([
+                ["</s>", 0.0],
+                ["<unk>", -12.5],
+                ["\u{2581}hello", -1.5],
+                [MISSING_PIECE, MISSING_SCORE],
+                ["\u{2581}world", -2.5],
+                ["<pad>", 0.0],
+            ])
+        );
+        let added: Vec<(u64, &str)> = tokenizer["added_tokens"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| (t["id"].as_u64().unwrap(), t["content"].as_str().unwrap()))
+            .collect();
+        assert_eq!(added, vec![(0, "</s>"), (1, "<unk>"), (5, "<pad>")]);
+        assert_eq!(
+            tokenizer["normalizer"]["normalizers"][0]["precompiled_charsmap"],
+            "YWJjZA=="
+        );
+        assert_eq!(tokenizer["pre_tokenizer"]["type"], "Metaspace");
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_sentencepiece_model() {
+        assert!(convert(b"{\"not\": \"protobuf\"}", &vocab()).is_err());
+        assert!(convert(&spm()[..20], &vocab()).is_err());
+    }
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+}
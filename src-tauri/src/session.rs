@@ -0,0 +1,306 @@
+use std::fmt::Write as _;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Track;
+
+/// A transcribed and translated piece of a recording, timed from `start_recording`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Segment {
+    #[serde(default)]
+    pub track: Track,
+    #[serde(rename = "startMs")]
+    pub start_ms: u64,
+    #[serde(rename = "endMs")]
+    pub end_ms: u64,
+    #[serde(rename = "originalText")]
+    pub original_text: String,
+    #[serde(rename = "translatedText")]
+    pub translated_text: String,
+}
+
+/// Collects the segments of the current recording so they can be exported.
+#[derive(Default)]
+pub struct Session {
+    segments: Vec<Segment>,
+}
+
+impl Session {
+    /// Drops the previous recording's segments.
+    pub fn start(&mut self) {
+        self.segments.clear();
+    }
+
+    /// Adds a segment, returning it as stored or `None` if it was dropped.
+    pub fn push(&mut self, mut segment: Segment) -> Option<&Segment> {
+        // Whisper windows overlap, clip so subtitle cues of a track never do.
+        // Tracks may talk over each other.
+        if let Some(last) = self
+            .segments
+            .iter()
+            .rev()
+            .find(|s| s.track == segment.track)
+        {
+            segment.start_ms = segment.start_ms.max(last.end_ms);
+        }
+        if segment.end_ms <= segment.start_ms {
+            return None;
+        }
+        self.segments.push(segment);
+        self.segments.last()
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Srt,
+    Vtt,
+    Txt,
+    Json,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportLanguage {
+    #[default]
+    Bilingual,
+    Original,
+    Translated,
+}
+
+impl ExportLanguage {
+    fn lines<'a>(&self, segment: &'a Segment) -> Vec<&'a str> {
+        match self {
+            Self::Bilingual => vec![&segment.original_text, &segment.translated_text],
+            Self::Original => vec![&segment.original_text],
+            Self::Translated => vec![&segment.translated_text],
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonSegment<'a> {
+    track: Track,
+    #[serde(rename = "startMs")]
+    start_ms: u64,
+    #[serde(rename = "endMs")]
+    end_ms: u64,
+    #[serde(rename = "originalText", skip_serializing_if = "Option::is_none")]
+    original_text: Option<&'a str>,
+    #[serde(rename = "translatedText", skip_serializing_if = "Option::is_none")]
+    translated_text: Option<&'a str>,
+}
+
+pub fn export(
+    segments: &[Segment],
+    format: ExportFormat,
+    language: ExportLanguage,
+) -> anyhow::Result<String> {
+    let mut out = String::new();
+    match format {
+        ExportFormat::Srt => {
+            for (i, segment) in segments.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{}\n{} --> {}",
+                    i + 1,
+                    timestamp(segment.start_ms, ','),
+                    timestamp(segment.end_ms, ',')
+                )?;
+                for line in language.lines(segment) {
+                    writeln!(out, "{}", line.trim())?;
+                }
+                writeln!(out)?;
+            }
+        }
+        ExportFormat::Vtt => {
+            writeln!(out, "WEBVTT\n")?;
+            for segment in segments {
+                writeln!(
+                    out,
+                    "{} --> {}",
+                    timestamp(segment.start_ms, '.'),
+                    timestamp(segment.end_ms, '.')
+                )?;
+                for line in language.lines(segment) {
+                    writeln!(out, "{}", line.trim())?;
+                }
+                writeln!(out)?;
+            }
+        }
+        ExportFormat::Txt => {
+            for segment in segments {
+                for line in language.lines(segment) {
+                    writeln!(out, "{}", line.trim())?;
+                }
+                if matches!(language, ExportLanguage::Bilingual) {
+                    writeln!(out)?;
+                }
+            }
+        }
+        ExportFormat::Json => {
+            let segments: Vec<JsonSegment> = segments
+                .iter()
+                .map(|segment| JsonSegment {
+                    track: segment.track,
+                    start_ms: segment.start_ms,
+                    end_ms: segment.end_ms,
+                    original_text: (!matches!(language, ExportLanguage::Translated))
+                        .then(|| segment.original_text.trim()),
+                    translated_text: (!matches!(language, ExportLanguage::Original))
+                        .then(|| segment.translated_text.trim()),
+                })
+                .collect();
+            out = serde_json::to_string_pretty(&segments)?;
+        }
+    }
+    Ok(out)
+}
+
+fn timestamp(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(
+        track: Track,
+        start_ms: u64,
+        end_ms: u64,
+        original: &str,
+        translated: &str,
+    ) -> Segment {
+        Segment {
+            track,
+            start_ms,
+            end_ms,
+            original_text: original.to_string(),
+            translated_text: translated.to_string(),
+        }
+    }
+
+    fn segments() -> Vec<Segment> {
+        vec![
+            segment(Track::Them, 999, 61_001, " Hello there.", " Hallo."),
+            segment(Track::Me, 3_599_999, 3_723_045, " Bye", " Tschüss "),
+        ]
+    }
+
+    #[test]
+    fn formats_timestamps_down_to_the_millisecond() {
+        assert_eq!(timestamp(0, ','), "00:00:00,000");
+        assert_eq!(timestamp(999, ','), "00:00:00,999");
+        assert_eq!(timestamp(59_999, '.'), "00:00:59.999");
+        assert_eq!(timestamp(3_599_999, '.'), "00:59:59.999");
+        assert_eq!(timestamp(3_600_000, ','), "01:00:00,000");
+        assert_eq!(timestamp(360_000_001, ','), "100:00:00,001");
+    }
+
+    #[test]
+    fn exports_srt() {
+        let srt = export(&segments(), ExportFormat::Srt, ExportLanguage::Bilingual).unwrap();
+        assert_eq!(
+            srt,
+            "1\n00:00:00,999 --> 00:01:01,001\nHello there.\nHallo.\n\n\
+             2\n00:59:59,999 --> 01:02:03,045\nBye\nTschüss\n\n"
+        );
+    }
+
+    #[test]
+    fn exports_webvtt() {
+        let vtt = export(&segments(), ExportFormat::Vtt, ExportLanguage::Translated).unwrap();
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n\
+             00:00:00.999 --> 00:01:01.001\nHallo.\n\n\
+             00:59:59.999 --> 01:02:03.045\nTschüss\n\n"
+        );
+    }
+
+    #[test]
+    fn exports_txt() {
+        let bilingual = export(&segments(), ExportFormat::Txt, ExportLanguage::Bilingual).unwrap();
+        assert_eq!(bilingual, "Hello there.\nHallo.\n\nBye\nTschüss\n\n");
+        let original = export(&segments(), ExportFormat::Txt, ExportLanguage::Original).unwrap();
+        assert_eq!(original, "Hello there.\nBye\n");
+    }
+
+    #[test]
+    fn exports_json() {
+        let json = export(
+            &segments()[..1],
+            ExportFormat::Json,
+            ExportLanguage::Original,
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"[
+  {
+    "track": "them",
+    "startMs": 999,
+    "endMs": 61001,
+    "originalText": "Hello there."
+  }
+]"#
+        );
+        let json = export(
+            &segments()[1..],
+            ExportFormat::Json,
+            ExportLanguage::Bilingual,
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"[
+  {
+    "track": "me",
+    "startMs": 3599999,
+    "endMs": 3723045,
+    "originalText": "Bye",
+    "translatedText": "Tschüss"
+  }
+]"#
+        );
+    }
+
+    #[test]
+    fn push_clips_overlapping_segments_of_the_same_track() {
+        let mut session = Session::default();
+        session
+            .push(segment(Track::Them, 0, 2000, " a", " a"))
+            .unwrap();
+        // Another track may overlap
+        let other = session
+            .push(segment(Track::Me, 1500, 2500, " b", " b"))
+            .unwrap();
+        assert_eq!((other.start_ms, other.end_ms), (1500, 2500));
+
+        let clipped = session
+            .push(segment(Track::Them, 1200, 3000, " c", " c"))
+            .unwrap();
+        assert_eq!((clipped.start_ms, clipped.end_ms), (2000, 3000));
+        // Nothing is left of a segment that ends before the previous one does
+        assert!(session
+            .push(segment(Track::Them, 2500, 3000, " d", " d"))
+            .is_none());
+        assert_eq!(session.segments().len(), 3);
+
+        session.start();
+        assert!(session.segments().is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Turns repeated transcriptions of a growing utterance into committed and
+//! tentative text.
+//!
+//! Uses LocalAgreement-2: words on which two consecutive hypotheses agree are
+//! committed and never change again, the rest stays tentative until the
+//! utterance ends.
+
+pub struct Hypothesis {
+    pub segment_id: u64,
+    pub committed: String,
+    pub tentative: String,
+}
+
+impl Hypothesis {
+    pub fn text(&self) -> String {
+        format!("{}{}", self.committed, self.tentative)
+    }
+}
+
+#[derive(Default)]
+pub struct Stabilizer {
+    segment_id: u64,
+    committed: Vec<String>,
+    previous: Vec<String>,
+}
+
+impl Stabilizer {
+    /// Feeds a transcription of the utterance so far.
+    pub fn update(&mut self, text: &str) -> Hypothesis {
+        let words = split_words(text);
+        let agreed = self
+            .previous
+            .iter()
+            .zip(&words)
+            .take_while(|(a, b)| a.trim() == b.trim())
+            .count();
+        if agreed > self.committed.len() {
+            self.committed = words[..agreed].to_vec();
+        }
+        let tentative = words[self.resume_at(&words)..].concat();
+        self.previous = words;
+        Hypothesis {
+            segment_id: self.segment_id,
+            committed: self.committed.concat(),
+            tentative,
+        }
+    }
+
+    /// Where the tentative part of `words` starts. Whisper may re-punctuate
+    /// committed text, so this looks for the last committed word instead of
+    /// just counting words. When whisper dropped that word, the tentative part
+    /// starts where `words` stops agreeing with the committed text, so no new
+    /// word is hidden behind a committed one.
+    fn resume_at(&self, words: &[String]) -> usize {
+        let Some(last) = self.committed.last() else {
+            return 0;
+        };
+        let matched = self
+            .committed
+            .iter()
+            .zip(words)
+            .take_while(|(a, b)| a.trim() == b.trim())
+            .count();
+        if matched == self.committed.len() {
+            return matched;
+        }
+        words
+            .iter()
+            .skip(matched)
+            .position(|w| w.trim() == last.trim())
+            .map(|i| matched + i + 1)
+            .unwrap_or(matched)
+    }
+
+    /// Commits the final transcription of the utterance and moves on to the next
+    /// segment. With no text the last hypothesis is committed instead, and
+    /// `None` is returned when nothing was shown for this segment at all.
+    pub fn finish(&mut self, text: Option<&str>) -> Option<Hypothesis> {
+        let words = match text {
+            Some(text) => split_words(text),
+            None if self.previous.is_empty() => return None,
+            None => std::mem::take(&mut self.previous),
+        };
+        let hypothesis = Hypothesis {
+            segment_id: self.segment_id,
+            committed: words.concat(),
+            tentative: String::new(),
+        };
+        self.segment_id += 1;
+        self.committed.clear();
+        self.previous.clear();
+        Some(hypothesis)
+    }
+}
+
+/// Splits into words that keep their leading whitespace, so concatenating
+/// them restores the text. CJK characters count as words of their own.
+pub fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        let boundary = c.is_whitespace() && !current.trim().is_empty();
+        if boundary || (is_cjk(c) && !current.trim().is_empty()) {
+            words.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+        if is_cjk(c) {
+            words.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.trim().is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commits_the_prefix_two_hypotheses_agree_on() {
+        let mut stabilizer = Stabilizer::default();
+        let first = stabilizer.update(" The quick brown");
+        assert_eq!(first.committed, "");
+        assert_eq!(first.tentative, " The quick brown");
+
+        let second = stabilizer.update(" The quick red fox");
+        assert_eq!(second.committed, " The quick");
+        assert_eq!(second.tentative, " red fox");
+        assert_eq!(second.text(), " The quick red fox");
+    }
+
+    #[test]
+    fn revises_the_tentative_tail_only() {
+        let mut stabilizer = Stabilizer::default();
+        stabilizer.update(" I want to");
+        stabilizer.update(" I want to go");
+        // Whisper drops a committed word, which stays committed, and the words
+        // after it stay tentative
+        let revised = stabilizer.update(" I want go home");
+        assert_eq!(revised.committed, " I want to");
+        assert_eq!(revised.tentative, " go home");
+        assert_eq!(revised.text(), " I want to go home");
+
+        // Re-punctuated committed text isn't shown twice
+        let repunctuated = stabilizer.update(" I, want to go home");
+        assert_eq!(repunctuated.committed, " I want to");
+        assert_eq!(repunctuated.tentative, " go home");
+    }
+
+    #[test]
+    fn splits_cjk_per_character() {
+        assert_eq!(split_words("你好世界"), ["你", "好", "世", "界"]);
+        assert_eq!(
+            split_words(" hello 世界 ok"),
+            [" hello", " 世", "界", " ok"]
+        );
+
+        let mut stabilizer = Stabilizer::default();
+        stabilizer.update("我们今天");
+        let hypothesis = stabilizer.update("我们明天");
+        assert_eq!(hypothesis.committed, "我们");
+        assert_eq!(hypothesis.tentative, "明天");
+    }
+
+    #[test]
+    fn finish_commits_everything_and_starts_a_new_segment() {
+        let mut stabilizer = Stabilizer::default();
+        stabilizer.update(" Hello");
+        stabilizer.update(" Hello there");
+        let last = stabilizer.finish(Some(" Hello there, friend")).unwrap();
+        assert_eq!(last.segment_id, 0);
+        assert_eq!(last.committed, " Hello there, friend");
+        assert_eq!(last.tentative, "");
+
+        let next = stabilizer.update(" Bye");
+        assert_eq!(next.segment_id, 1);
+        assert_eq!(next.committed, "");
+    }
+
+    #[test]
+    fn finish_without_text_keeps_the_last_hypothesis() {
+        let mut stabilizer = Stabilizer::default();
+        assert!(stabilizer.finish(None).is_none());
+
+        stabilizer.update(" Almost done");
+        let last = stabilizer.finish(None).unwrap();
+        assert_eq!(last.committed, " Almost done");
+        // Nothing was shown before, so no segment was used up
+        assert_eq!(last.segment_id, 0);
+        assert!(stabilizer.finish(None).is_none());
+    }
+}
@@ -1,61 +1,239 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
 use anyhow::Error as E;
 use candle_core::{DType, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::models::marian::{self, MTModel};
+use serde_json::Value;
 use tokenizers::Tokenizer;
 
+use crate::device::{self, ComputeDevice, DevicePreference};
+
+/// Settings of a Marian checkpoint.
+#[derive(Clone, Debug)]
+pub struct ModelConfig {
+    pub marian: marian::Config,
+    /// Upper bound on generated tokens
+    pub max_length: usize,
+}
+
+impl ModelConfig {
+    /// Reads `config.json` and `generation_config.json` belonging to `model`, using
+    /// `fallback` when the checkpoint comes without them.
+    ///
+    /// For a model directory these are the files inside it, for a single
+    /// `name.bin` file they are `name.config.json` and `name.generation_config.json`.
+    pub fn load(model: &Path, fallback: Option<&marian::Config>) -> anyhow::Result<Self> {
+        let (config_path, generation_path) = if model.is_dir() {
+            (
+                model.join("config.json"),
+                model.join("generation_config.json"),
+            )
+        } else {
+            (
+                model.with_extension("config.json"),
+                model.with_extension("generation_config.json"),
+            )
+        };
+
+        let mut marian = if config_path.exists() {
+            log::info!("read marian config from {}", config_path.display());
+            let mut config: Value = serde_json::from_str(&std::fs::read_to_string(&config_path)?)?;
+            if let Value::Object(config) = &mut config {
+                // Older Marian configs leave these out and rely on transformers defaults
+                let eos = config
+                    .get("eos_token_id")
+                    .cloned()
+                    .unwrap_or(Value::from(0));
+                let pad = config
+                    .get("pad_token_id")
+                    .cloned()
+                    .unwrap_or(Value::from(0));
+                for (key, default) in [
+                    ("use_cache", Value::from(true)),
+                    ("is_encoder_decoder", Value::from(true)),
+                    ("activation_function", Value::from("swish")),
+                    ("scale_embedding", Value::from(true)),
+                    ("share_encoder_decoder_embeddings", Value::from(true)),
+                    ("forced_eos_token_id", eos),
+                    ("decoder_start_token_id", pad),
+                ] {
+                    config.entry(key).or_insert(default);
+                }
+            }
+            serde_json::from_value::<marian::Config>(config)?
+        } else {
+            fallback
+                .cloned()
+                .ok_or_else(|| E::msg(format!("{} not found", config_path.display())))?
+        };
+
+        let mut max_length = 512;
+        if generation_path.exists() {
+            log::info!("read generation config from {}", generation_path.display());
+            let generation: Value =
+                serde_json::from_str(&std::fs::read_to_string(&generation_path)?)?;
+            let token_id = |key: &str| -> Option<u32> {
+                // eos_token_id may be a list, the first entry is the one Marian uses
+                match generation.get(key)? {
+                    Value::Array(ids) => ids.first()?.as_u64(),
+                    value => value.as_u64(),
+                }
+                .map(|id| id as u32)
+            };
+            if let Some(id) = token_id("decoder_start_token_id") {
+                marian.decoder_start_token_id = id;
+            }
+            if let Some(id) = token_id("eos_token_id") {
+                marian.eos_token_id = id;
+            }
+            if let Some(id) = token_id("forced_eos_token_id") {
+                marian.forced_eos_token_id = id;
+            }
+            if let Some(id) = token_id("pad_token_id") {
+                marian.pad_token_id = id;
+            }
+            if let Some(len) = generation.get("max_length").and_then(Value::as_u64) {
+                max_length = len as usize;
+            }
+        }
+
+        let max_length = max_length.min(marian.max_position_embeddings);
+        Ok(Self { marian, max_length })
+    }
+
+    /// Checks the config against the tensor shapes stored in the safetensors file.
+    fn validate(&self, weights: &Path) -> anyhow::Result<()> {
+        let config = &self.marian;
+        let st = unsafe { candle_core::safetensors::MmapedSafetensors::new(weights)? };
+        let expect = |name: &str, shape: &[usize]| -> anyhow::Result<()> {
+            let view = st.get(name)?;
+            if view.shape() != shape {
+                anyhow::bail!(
+                    "{name} has shape {:?} but the config expects {:?}",
+                    view.shape(),
+                    shape
+                );
+            }
+            Ok(())
+        };
+        let target_vocab = config.decoder_vocab_size.unwrap_or(config.vocab_size);
+        expect("final_logits_bias", &[1, target_vocab])?;
+        expect("model.shared.weight", &[config.vocab_size, config.d_model])?;
+        expect(
+            "model.encoder.layers.0.fc1.weight",
+            &[config.encoder_ffn_dim, config.d_model],
+        )?;
+        expect(
+            "model.decoder.layers.0.fc1.weight",
+            &[config.decoder_ffn_dim, config.d_model],
+        )?;
+
+        let names: Vec<String> = st.tensors().into_iter().map(|(name, _)| name).collect();
+        for (part, layers) in [
+            ("encoder", config.encoder_layers),
+            ("decoder", config.decoder_layers),
+        ] {
+            let prefix = format!("model.{part}.layers.");
+            let found = names
+                .iter()
+                .filter_map(|n| {
+                    n.strip_prefix(&prefix)?
+                        .split('.')
+                        .next()?
+                        .parse::<usize>()
+                        .ok()
+                })
+                .max()
+                .map_or(0, |i| i + 1);
+            if found != layers {
+                anyhow::bail!(
+                    "checkpoint has {found} {part} layers but the config expects {layers}"
+                );
+            }
+        }
+        for heads in [
+            config.encoder_attention_heads,
+            config.decoder_attention_heads,
+        ] {
+            if heads == 0 || config.d_model % heads != 0 {
+                anyhow::bail!(
+                    "d_model {} is not divisible by {heads} heads",
+                    config.d_model
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The safetensors file of a model given as a file or a Hugging Face style directory.
+fn weights_path(model: &Path) -> PathBuf {
+    if model.is_dir() {
+        model.join("model.safetensors")
+    } else {
+        model.to_path_buf()
+    }
+}
+
+/// Writes the tensors of a PyTorch `pytorch_model.bin` to a safetensors file.
+/// The file only appears once it's complete.
+pub fn convert_pytorch(checkpoint: &Path, weights: &Path) -> anyhow::Result<()> {
+    log::info!("convert {} to {}", checkpoint.display(), weights.display());
+    let tensors: HashMap<String, Tensor> = candle_core::pickle::read_all(checkpoint)?
+        .into_iter()
+        .collect();
+    let partial = weights.with_extension("safetensors.tmp");
+    candle_core::safetensors::save(&tensors, &partial)?;
+    std::fs::rename(&partial, weights)?;
+    Ok(())
+}
+
 pub struct Translator {
     model: MTModel,
     config: marian::Config,
+    max_length: usize,
     tokenizer: Tokenizer,
     tokenizer_dec: Tokenizer,
     device: candle_core::Device,
+    compute_device: ComputeDevice,
 }
 
 impl Translator {
-    pub fn new(model: &str, en_token: &str, zh_token: &str) -> anyhow::Result<Self> {
-        let tokenizer = Tokenizer::from_file(en_token).map_err(E::msg)?;
-        let tokenizer_dec = Tokenizer::from_file(zh_token).map_err(E::msg)?;
+    pub fn new(
+        model: &str,
+        config: ModelConfig,
+        source_token: &str,
+        target_token: &str,
+        preference: DevicePreference,
+    ) -> anyhow::Result<Self> {
+        let tokenizer = Tokenizer::from_file(source_token).map_err(E::msg)?;
+        let tokenizer_dec = Tokenizer::from_file(target_token).map_err(E::msg)?;
         // let tokenizer_dec = TokenOutputStream::new(tokenizer_dec);
-        let device = if cfg!(target_os = "macos") {
-            candle_core::Device::new_metal(0)?
-        } else {
-            candle_core::Device::new_cuda(0)?
-        };
-        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[&model], DType::F32, &device)? };
-        // https://huggingface.co/Helsinki-NLP/opus-mt-en-zh/blob/main/config.json
-        let config = marian::Config {
-            vocab_size: 65001,
-            decoder_vocab_size: Some(65001),
-            max_position_embeddings: 512,
-            encoder_layers: 6,
-            encoder_ffn_dim: 2048,
-            encoder_attention_heads: 8,
-            decoder_layers: 6,
-            decoder_ffn_dim: 2048,
-            decoder_attention_heads: 8,
-            use_cache: true,
-            is_encoder_decoder: true,
-            activation_function: candle_nn::Activation::Swish,
-            d_model: 512,
-            decoder_start_token_id: 65000,
-            scale_embedding: true,
-            pad_token_id: 65000,
-            eos_token_id: 0,
-            forced_eos_token_id: 0,
-            share_encoder_decoder_embeddings: true,
-        };
-        let model = marian::MTModel::new(&config, vb)?;
+        let (device, compute_device) = device::candle_device(preference)?;
+        let weights = weights_path(Path::new(model));
+        config.validate(&weights)?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[&weights], DType::F32, &device)? };
+        let model = marian::MTModel::new(&config.marian, vb)?;
         Ok(Self {
             model,
-            config,
+            config: config.marian,
+            max_length: config.max_length,
             tokenizer,
             tokenizer_dec,
             device,
+            compute_device,
         })
     }
 
+    pub fn device(&self) -> ComputeDevice {
+        self.compute_device
+    }
+
     pub fn translate(&mut self, text: &str) -> anyhow::Result<String> {
+        // A translation that failed midway may have left its cache behind
+        self.model.reset_kv_cache();
         let mut logits_processor =
             candle_transformers::generation::LogitsProcessor::new(1337, None, None);
         let encoder_xs = {
@@ -70,7 +248,7 @@ impl Translator {
             self.model.encoder().forward(&tokens, 0)?
         };
         let mut token_ids = vec![self.config.decoder_start_token_id];
-        for index in 0..1000 {
+        for index in 0..self.max_length {
             let context_size = if index >= 1 { 1 } else { token_ids.len() };
             let start_pos = token_ids.len().saturating_sub(context_size);
             let input_ids = Tensor::new(&token_ids[start_pos..], &self.device)?.unsqueeze(0)?;
@@ -93,7 +271,165 @@ impl Translator {
             .tokenizer_dec
             .decode(&token_ids[1..], true)
             .map_err(E::msg)?;
-        self.model.reset_kv_cache();
         Ok(ans)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("peeches-translate-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A config.json the way older Marian checkpoints ship it.
+    fn config_json() -> Value {
+        json!({
+            "vocab_size": 10,
+            "max_position_embeddings": 16,
+            "encoder_layers": 2,
+            "encoder_ffn_dim": 8,
+            "encoder_attention_heads": 2,
+            "decoder_layers": 1,
+            "decoder_ffn_dim": 6,
+            "decoder_attention_heads": 2,
+            "d_model": 4,
+            "pad_token_id": 9,
+            "eos_token_id": 1,
+        })
+    }
+
+    fn write_json(path: &Path, value: &Value) {
+        std::fs::write(path, value.to_string()).unwrap();
+    }
+
+    #[test]
+    fn fills_in_keys_older_configs_leave_out() {
+        let dir = temp_dir("defaults");
+        write_json(&dir.join("config.json"), &config_json());
+        let config = ModelConfig::load(&dir, None).unwrap();
+        assert_eq!(config.marian.forced_eos_token_id, 1);
+        assert_eq!(config.marian.decoder_start_token_id, 9);
+        assert!(config.marian.scale_embedding);
+        assert!(config.marian.share_encoder_decoder_embeddings);
+        assert_eq!(config.marian.decoder_vocab_size, None);
+        // Capped at what the position embeddings allow
+        assert_eq!(config.max_length, 16);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn generation_config_overrides_token_ids() {
+        let dir = temp_dir("generation");
+        let model = dir.join("opus-mt-en-xx.bin");
+        write_json(&dir.join("opus-mt-en-xx.config.json"), &config_json());
+        write_json(
+            &dir.join("opus-mt-en-xx.generation_config.json"),
+            &json!({ "eos_token_id": [2, 3], "decoder_start_token_id": 8, "max_length": 12 }),
+        );
+        let config = ModelConfig::load(&model, None).unwrap();
+        assert_eq!(config.marian.eos_token_id, 2);
+        assert_eq!(config.marian.decoder_start_token_id, 8);
+        assert_eq!(config.marian.pad_token_id, 9);
+        assert_eq!(config.max_length, 12);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reports_missing_keys_and_files() {
+        let dir = temp_dir("missing");
+        let mut incomplete = config_json();
+        incomplete.as_object_mut().unwrap().remove("d_model");
+        write_json(&dir.join("config.json"), &incomplete);
+        let err = ModelConfig::load(&dir, None).unwrap_err();
+        assert!(err.to_string().contains("d_model"), "{err}");
+
+        std::fs::remove_file(dir.join("config.json")).unwrap();
+        let err = ModelConfig::load(&dir, None).unwrap_err();
+        assert!(err.to_string().contains("not found"), "{err}");
+
+        let mut complete = config_json();
+        for (key, value) in [
+            ("use_cache", json!(true)),
+            ("is_encoder_decoder", json!(true)),
+            ("activation_function", json!("swish")),
+            ("scale_embedding", json!(true)),
+            ("share_encoder_decoder_embeddings", json!(true)),
+            ("forced_eos_token_id", json!(1)),
+            ("decoder_start_token_id", json!(9)),
+        ] {
+            complete[key] = value;
+        }
+        let fallback: marian::Config = serde_json::from_value(complete).unwrap();
+        let config = ModelConfig::load(&dir, Some(&fallback)).unwrap();
+        assert_eq!(config.marian.d_model, 4);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn write_weights(path: &Path, shapes: &[(&str, &[usize])]) {
+        let tensors: HashMap<String, Tensor> = shapes
+            .iter()
+            .map(|(name, shape)| {
+                let tensor = Tensor::zeros(*shape, DType::F32, &candle_core::Device::Cpu).unwrap();
+                (name.to_string(), tensor)
+            })
+            .collect();
+        candle_core::safetensors::save(&tensors, path).unwrap();
+    }
+
+    #[test]
+    fn validate_compares_the_config_with_the_weights() {
+        let dir = temp_dir("validate");
+        write_json(&dir.join("config.json"), &config_json());
+        let config = ModelConfig::load(&dir, None).unwrap();
+        let weights = dir.join("model.safetensors");
+        let layers: [(&str, &[usize]); 3] = [
+            ("model.encoder.layers.0.fc1.weight", &[8, 4]),
+            ("model.encoder.layers.1.fc1.weight", &[8, 4]),
+            ("model.decoder.layers.0.fc1.weight", &[6, 4]),
+        ];
+
+        let mut shapes: Vec<(&str, &[usize])> = vec![
+            ("final_logits_bias", &[1, 10]),
+            ("model.shared.weight", &[10, 4]),
+        ];
+        shapes.extend(layers);
+        write_weights(&weights, &shapes);
+        config.validate(&weights).unwrap();
+
+        // Weights of a model with a different vocabulary
+        let mut shapes: Vec<(&str, &[usize])> = vec![
+            ("final_logits_bias", &[1, 12]),
+            ("model.shared.weight", &[12, 4]),
+        ];
+        shapes.extend(layers);
+        write_weights(&weights, &shapes);
+        let err = config.validate(&weights).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "final_logits_bias has shape [1, 12] but the config expects [1, 10]"
+        );
+
+        // And with a layer more
+        let mut shapes: Vec<(&str, &[usize])> = vec![
+            ("final_logits_bias", &[1, 10]),
+            ("model.shared.weight", &[10, 4]),
+        ];
+        shapes.extend(layers);
+        shapes.push(("model.decoder.layers.1.fc1.weight", &[6, 4]));
+        write_weights(&weights, &shapes);
+        let err = config.validate(&weights).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "checkpoint has 2 decoder layers but the config expects 1"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,163 @@
+//! Energy based voice activity detection.
+//!
+//! Tracks the background noise level and treats frames that are clearly louder
+//! as speech, with a hangover so short gaps between words don't end an utterance.
+
+/// 20ms at 16kHz
+pub const FRAME_SAMPLES: usize = 320;
+
+pub struct VadConfig {
+    /// How far above the noise floor a frame must be to count as speech
+    pub threshold_db: f32,
+    /// Frames quieter than this are never speech
+    pub min_level_db: f32,
+    /// Consecutive loud frames needed before speech starts
+    pub start_frames: usize,
+    /// Consecutive quiet frames needed before speech ends
+    pub end_frames: usize,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: 9.0,
+            min_level_db: -55.0,
+            start_frames: 3,
+            end_frames: 25,
+        }
+    }
+}
+
+pub struct Vad {
+    config: VadConfig,
+    noise_floor_db: f32,
+    speaking: bool,
+    loud_frames: usize,
+    quiet_frames: usize,
+}
+
+impl Default for Vad {
+    fn default() -> Self {
+        Self::new(VadConfig::default())
+    }
+}
+
+impl Vad {
+    pub fn new(config: VadConfig) -> Self {
+        Self {
+            config,
+            noise_floor_db: -60.0,
+            speaking: false,
+            loud_frames: 0,
+            quiet_frames: 0,
+        }
+    }
+
+    /// Feeds one frame and returns whether it belongs to speech.
+    pub fn process(&mut self, frame: &[f32]) -> bool {
+        let level = level_db(frame);
+        let loud = level > self.config.min_level_db
+            && level > self.noise_floor_db + self.config.threshold_db;
+
+        // Follow the floor down immediately, up only slowly and outside of speech
+        if level < self.noise_floor_db {
+            self.noise_floor_db = level;
+        } else if !loud {
+            self.noise_floor_db = self.noise_floor_db * 0.95 + level * 0.05;
+        }
+
+        if loud {
+            self.loud_frames += 1;
+            self.quiet_frames = 0;
+        } else {
+            self.loud_frames = 0;
+            self.quiet_frames += 1;
+        }
+        if !self.speaking && self.loud_frames >= self.config.start_frames {
+            self.speaking = true;
+        } else if self.speaking && self.quiet_frames >= self.config.end_frames {
+            self.speaking = false;
+        }
+        self.speaking
+    }
+}
+
+fn level_db(frame: &[f32]) -> f32 {
+    if frame.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+    20.0 * (rms + 1e-10).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame of a 440Hz tone whose level is `db`.
+    fn tone(db: f32) -> Vec<f32> {
+        let amplitude = 10f32.powf(db / 20.0) * std::f32::consts::SQRT_2;
+        (0..FRAME_SAMPLES)
+            .map(|i| amplitude * (i as f32 * 440.0 * std::f32::consts::TAU / 16000.0).sin())
+            .collect()
+    }
+
+    fn run(vad: &mut Vad, frame: &[f32], count: usize) -> Vec<bool> {
+        (0..count).map(|_| vad.process(frame)).collect()
+    }
+
+    #[test]
+    fn measures_the_level_of_a_frame() {
+        assert!((level_db(&tone(-20.0)) + 20.0).abs() < 0.2);
+        assert!(level_db(&[0.0; FRAME_SAMPLES]) < -150.0);
+        assert_eq!(level_db(&[]), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn quiet_sounds_are_never_speech() {
+        let mut vad = Vad::default();
+        run(&mut vad, &[0.0; FRAME_SAMPLES], 10);
+        // Far above the floor of digital silence, but under min_level_db
+        assert!(!run(&mut vad, &tone(-58.0), 50).contains(&true));
+    }
+
+    #[test]
+    fn speech_has_to_stand_out_from_the_noise_floor() {
+        let mut vad = Vad::default();
+        // The floor rises slowly to steady background noise
+        assert!(!run(&mut vad, &tone(-53.0), 200).contains(&true));
+        // Louder than min_level_db, but not by threshold_db above the floor
+        assert!(!run(&mut vad, &tone(-46.0), 5).contains(&true));
+        assert!(run(&mut vad, &tone(-40.0), 3).contains(&true));
+    }
+
+    #[test]
+    fn starts_after_start_frames_and_ends_after_end_frames() {
+        let mut vad = Vad::default();
+        let config = VadConfig::default();
+        run(&mut vad, &[0.0; FRAME_SAMPLES], 10);
+
+        let onset = run(&mut vad, &tone(-20.0), config.start_frames);
+        assert_eq!(onset.iter().filter(|&&speech| speech).count(), 1);
+        assert!(onset[config.start_frames - 1]);
+
+        // A gap shorter than the hangover doesn't end the speech
+        let hangover = run(&mut vad, &[0.0; FRAME_SAMPLES], config.end_frames - 1);
+        assert!(!hangover.contains(&false));
+        assert!(vad.process(&tone(-20.0)));
+
+        let gap = run(&mut vad, &[0.0; FRAME_SAMPLES], config.end_frames);
+        assert!(!gap[..config.end_frames - 1].contains(&false));
+        assert!(!gap[config.end_frames - 1]);
+    }
+
+    #[test]
+    fn a_single_loud_frame_is_not_speech() {
+        let mut vad = Vad::default();
+        run(&mut vad, &[0.0; FRAME_SAMPLES], 10);
+        for _ in 0..5 {
+            assert!(!vad.process(&tone(-20.0)));
+            assert!(!vad.process(&[0.0; FRAME_SAMPLES]));
+        }
+    }
+}
@@ -4,48 +4,201 @@ use whisper_rs::{
     FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
 };
 
+use crate::device::{ComputeDevice, DevicePreference};
+use crate::segmenter::Timing;
+
+/// Lets whisper pick the spoken language for every window.
+pub const AUTO_LANGUAGE: &str = "auto";
+
+pub struct Transcript {
+    pub segments: Vec<TranscriptSegment>,
+    /// Language the audio was transcribed as, the detected one in auto mode
+    pub language: String,
+}
+
+impl Transcript {
+    /// Text of all segments joined together.
+    pub fn text(&self) -> String {
+        self.segments.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    /// The text, or `None` when whisper heard nothing.
+    pub fn speech_text(&self) -> Option<String> {
+        let text = self.text();
+        let trimmed = text.trim();
+        (!trimmed.is_empty() && trimmed != "[BLANK_AUDIO]").then_some(text)
+    }
+
+    /// Where the text of each segment ends in `text()`, with its time from
+    /// `offset_ms`.
+    pub fn timings(&self, offset_ms: u64) -> Vec<Timing> {
+        let mut end = 0;
+        self.segments
+            .iter()
+            .map(|segment| {
+                end += segment.text.len();
+                Timing {
+                    end,
+                    start_ms: offset_ms + segment.start_ms,
+                    end_ms: offset_ms + segment.end_ms,
+                }
+            })
+            .collect()
+    }
+
+    /// Start and end of the speech within samples `len_ms` long, the whole
+    /// of them when there are no segments.
+    pub fn speech_span(&self, len_ms: u64) -> (u64, u64) {
+        let start_ms = self.segments.first().map_or(0, |s| s.start_ms.min(len_ms));
+        let end_ms = self
+            .segments
+            .last()
+            .map_or(len_ms, |s| s.end_ms.clamp(start_ms, len_ms));
+        (start_ms, end_ms)
+    }
+}
+
+pub struct TranscriptSegment {
+    pub text: String,
+    /// Start relative to the transcribed samples
+    pub start_ms: u64,
+    /// End relative to the transcribed samples
+    pub end_ms: u64,
+    /// Text tokens, without whisper's special and timestamp tokens
+    pub tokens: Vec<Token>,
+    /// Probability that the segment contains no speech at all
+    pub no_speech_probability: f32,
+}
+
+impl TranscriptSegment {
+    pub fn avg_log_probability(&self) -> f32 {
+        if self.tokens.is_empty() {
+            return f32::NEG_INFINITY;
+        }
+        self.tokens.iter().map(|t| t.probability.ln()).sum::<f32>() / self.tokens.len() as f32
+    }
+
+    /// Whisper's own silence rule: likely no speech and not confidently decoded.
+    pub fn is_likely_silence(&self) -> bool {
+        self.no_speech_probability > 0.6 && self.avg_log_probability() < -1.0
+    }
+}
+
+pub struct Token {
+    pub text: String,
+    pub probability: f32,
+}
+
 pub struct Whisper {
-    // vad: Arc<Mutex<Vad>>,
     whisper_ctx: WhisperState,
     // normalizer: Arc<Mutex<Normalizer>>,
+    device: ComputeDevice,
+    language: String,
 }
 
 impl Whisper {
-    pub fn new(whisper_model_path: &str) -> Self {
-        // let vad = Vad::new(vad_model_path, 16000).unwrap();
+    pub fn new(whisper_model_path: &str, preference: DevicePreference) -> anyhow::Result<Self> {
         // let normalizer = Normalizer::new(1, 16000);
+        let gpu = ComputeDevice::gpu().filter(|_| preference != DevicePreference::Cpu);
+        if gpu.is_none() && preference == DevicePreference::Gpu {
+            anyhow::bail!("no GPU backend in this build");
+        }
+        let (state, device) = match Self::create_state(whisper_model_path, gpu.is_some()) {
+            Ok(state) => (state, gpu.unwrap_or(ComputeDevice::Cpu)),
+            Err(e) if gpu.is_some() && preference == DevicePreference::Auto => {
+                log::warn!("whisper failed on GPU, falling back to CPU: {}", e);
+                (
+                    Self::create_state(whisper_model_path, false)?,
+                    ComputeDevice::Cpu,
+                )
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            whisper_ctx: state,
+            // params: Arc::new(Mutex::new(params)),
+            // normalizer: Arc::new(Mutex::new(normalizer)),
+            device,
+            language: "en".to_string(),
+        })
+    }
+
+    fn create_state(whisper_model_path: &str, use_gpu: bool) -> anyhow::Result<WhisperState> {
         let ctx = WhisperContext::new_with_params(
             whisper_model_path,
             WhisperContextParameters {
-                use_gpu: true,
+                use_gpu,
                 flash_attn: false,
                 ..Default::default()
             },
-        )
-        .unwrap();
-        let state = ctx.create_state().expect("failed to create key");
+        )?;
+        Ok(ctx.create_state()?)
+    }
 
-        Self {
-            // vad: Arc::new(Mutex::new(vad)),
-            whisper_ctx: state,
-            // params: Arc::new(Mutex::new(params)),
-            // normalizer: Arc::new(Mutex::new(normalizer)),
+    pub fn device(&self) -> ComputeDevice {
+        self.device
+    }
+
+    /// Sets the spoken language, either an ISO 639-1 code or [`AUTO_LANGUAGE`].
+    pub fn set_language(&mut self, language: &str) -> anyhow::Result<()> {
+        if language != AUTO_LANGUAGE && whisper_rs::get_lang_id(language).is_none() {
+            anyhow::bail!("unsupported language '{language}'");
         }
+        self.language = language.to_string();
+        Ok(())
     }
 
-    pub fn transcribe(&mut self, samples: Vec<f32>) -> anyhow::Result<String> {
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn transcribe(&mut self, samples: Vec<f32>) -> anyhow::Result<Transcript> {
         let mut params = FullParams::new(SamplingStrategy::default());
         params.set_print_progress(false);
         params.set_print_realtime(false);
         params.set_print_special(false);
         params.set_print_timestamps(false);
         params.set_debug_mode(false);
-        params.set_language(Some("en"));
+        // With "auto" whisper detects the language as part of the transcription
+        params.set_language(Some(&self.language));
         // params.set_duration_ms(3000);
         params.set_logprob_thold(-2.0);
         params.set_temperature(0.0);
         self.whisper_ctx.full(params, &samples)?;
-        let text = self.whisper_ctx.full_get_segment_text_lossy(0)?;
-        Ok(text)
+        let language = if self.language == AUTO_LANGUAGE {
+            let id = self.whisper_ctx.full_lang_id_from_state()?;
+            whisper_rs::get_lang_str(id)
+                .ok_or_else(|| anyhow::Error::msg(format!("unknown language id {id}")))?
+                .to_string()
+        } else {
+            self.language.clone()
+        };
+        let segments = (0..self.whisper_ctx.full_n_segments()?)
+            .map(|i| self.segment(i))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Transcript { segments, language })
+    }
+
+    fn segment(&self, i: i32) -> anyhow::Result<TranscriptSegment> {
+        let state = &self.whisper_ctx;
+        let mut tokens = Vec::new();
+        for j in 0..state.full_n_tokens(i)? {
+            let text = state.full_get_token_text_lossy(i, j)?;
+            // Special tokens look like [_BEG_], [_TT_42] or <|endoftext|>
+            if text.starts_with("[_") || text.starts_with("<|") {
+                continue;
+            }
+            let probability = state.full_get_token_data(i, j)?.p;
+            tokens.push(Token { text, probability });
+        }
+        // Timestamps are in units of 10ms
+        Ok(TranscriptSegment {
+            text: state.full_get_segment_text_lossy(i)?,
+            start_ms: state.full_get_segment_t0(i)?.max(0) as u64 * 10,
+            end_ms: state.full_get_segment_t1(i)?.max(0) as u64 * 10,
+            tokens,
+            no_speech_probability: state.full_get_segment_no_speech_prob(i)?,
+        })
     }
 }
@@ -2,13 +2,18 @@ import { useEffect, useRef, useCallback, useState } from 'react';
 import { listen } from '@tauri-apps/api/event';
 import './History.css';
 
-type LyricsEvent = {
+type Track = "me" | "them" | "mixed";
+
+type TranscriptEvent = {
+    sentenceId: number;
+    track: Track;
     originalText: string;
     translatedText: string;
 };
 
 interface HistoryItem {
     id: string;
+    track: Track;
     originalText: string;
     translatedText: string;
     timestamp: number;
@@ -18,58 +23,39 @@ function History() {
     const [history, setHistory] = useState<HistoryItem[]>([]);
     const [isAutoScrollEnabled, setIsAutoScrollEnabled] = useState<boolean>(true);
     const [highlightedIndex, setHighlightedIndex] = useState<number>(-1);
-    const [_, setTranscriptionCounter] = useState<number>(0);
     const containerRef = useRef<HTMLDivElement>(null);
     const itemRefs = useRef<(HTMLDivElement | null)[]>([]);
     const userScrollTimeout = useRef<number | null>(null);
 
-    // Listen for new lyrics events and add to history
+    // Add each finalized sentence to history
     useEffect(() => {
-        const unlisten = listen<LyricsEvent>("event", (event) => {
-            const { originalText, translatedText } = event.payload;
-
-            // Check if this is valid content to add to history
-            const isValidContent = originalText &&
-                translatedText &&
-                originalText.trim() !== "" &&
-                translatedText.trim() !== "" &&
-                originalText !== "wait for audio" &&
-                originalText !== "已暂停" &&
-                originalText !== "BLANK_AUDIO" &&
-                translatedText !== "等待音频" &&
-                translatedText !== "空白";
-
-            if (isValidContent) {
-                setTranscriptionCounter(prev => {
-                    const newCounter = prev + 1;
-
-                    // Only add to history every 6 transcriptions
-                    if (newCounter % 4 === 0) {
-                        const newItem: HistoryItem = {
-                            id: Date.now().toString() + Math.random().toString(36).substr(2, 9),
-                            originalText,
-                            translatedText,
-                            timestamp: Date.now(),
-                        };
-
-                        setHistory(prevHistory => {
-                            const updatedHistory = [...prevHistory, newItem];
-
-                            // Auto-scroll and highlight if enabled
-                            if (isAutoScrollEnabled) {
-                                setTimeout(() => {
-                                    setHighlightedIndex(updatedHistory.length - 1); // Will be the new item's index
-                                    scrollToItem(updatedHistory.length - 1);
-                                }, 100);
-                            }
-
-                            return updatedHistory;
-                        });
-                    }
-
-                    return newCounter;
-                });
+        const unlisten = listen<TranscriptEvent>("final", (event) => {
+            const { sentenceId, track, originalText, translatedText } = event.payload;
+            if (originalText.trim() === "") {
+                return;
             }
+
+            const newItem: HistoryItem = {
+                id: `${track}-${sentenceId}`,
+                track,
+                originalText,
+                translatedText,
+                timestamp: Date.now(),
+            };
+
+            setHistory(prevHistory => {
+                const updatedHistory = [...prevHistory, newItem];
+
+                // Auto-scroll and highlight if enabled
+                if (isAutoScrollEnabled) {
+                    setTimeout(() => {
+                        setHighlightedIndex(updatedHistory.length - 1); // Will be the new item's index
+                        scrollToItem(updatedHistory.length - 1);
+                    }, 100);
+                }
+
+                return updatedHistory;
+            });
         });
 
         return () => {
@@ -177,6 +163,7 @@ function History() {
                                     {new Date(item.timestamp).toLocaleTimeString('zh-CN', { hour12: false, hour: '2-digit', minute: '2-digit', second: '2-digit', fractionalSecondDigits: 3 })}
                                 </div> */}
                                 <div className="item-original">
+                                    {item.track === "me" && <span className="item-track">我：</span>}
                                     {item.originalText}
                                 </div>
                                 <div className="item-translated">
@@ -5,6 +5,7 @@ import { useLyrics } from "../hooks/useLyrics";
 function Lyrics() {
   const {
     originalText,
+    tentativeText,
     translatedText,
     isPinned,
     isRecording,
@@ -86,7 +87,14 @@ function Lyrics() {
           className="original-text"
           {...(!isPinned && { "data-tauri-drag-region": true })}
         >
-          {originalText || "等待输入..."}
+          {originalText || tentativeText ? (
+            <>
+              {originalText}
+              <span className="tentative-text">{tentativeText}</span>
+            </>
+          ) : (
+            "等待输入..."
+          )}
         </div>
         <div
           className="translated-text"
@@ -2,11 +2,220 @@ import "./Settings.css";
 import { useModels } from "../hooks/useModels";
 import { useAtom } from "jotai";
 import { modelValuesAtom } from "../store/atoms";
-import { useEffect } from "react";
+import { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+
+type DevicePreference = "auto" | "gpu" | "cpu";
+
+type ResampleQuality = "best" | "medium" | "fastest" | "linear";
+
+type CaptureMode = "system" | "dual" | "mixed";
+
+type RecordingFormat = "wav" | "opus";
+
+type WhisperModel = {
+  fileName: string;
+  size: string;
+  quantization: string | null;
+  sizeMb: number;
+  downloaded: boolean;
+  active: boolean;
+};
+
+type CaptionServerSettings = {
+  port: number;
+  token: string;
+};
+
+type ImportKind = "whisper" | "translation";
+
+type TranslationPair = {
+  id: string;
+  source: string;
+  target: string;
+  downloaded: boolean;
+  active: boolean;
+};
+
+type AudioDevice = {
+  id: string;
+  name: string;
+  kind: "output" | "monitor" | "input";
+  isDefault: boolean;
+};
+
+type AudioDeviceList = {
+  devices: AudioDevice[];
+  selected: string | null;
+  mic: string | null;
+};
+
+type CaptureTarget = {
+  id: string;
+  name: string;
+  pid: number | null;
+};
+
+type CaptureTargetList = {
+  targets: CaptureTarget[];
+  selected: string | null;
+};
+
+type DeviceStatus = {
+  preference: DevicePreference;
+  whisper: string | null;
+  translator: string | null;
+};
+
+function whisperModelLabel(model: WhisperModel) {
+  return model.quantization ? `${model.size} ${model.quantization}` : model.size;
+}
 
 function Settings() {
-  const { downloadModel, verifyAndSyncModels } = useModels();
+  const { addModel, downloadModel, cancelDownload, deleteModel, verifyAndSyncModels } =
+    useModels();
   const [modelValues] = useAtom(modelValuesAtom);
+  const [device, setDevice] = useState<DeviceStatus | null>(null);
+  const [pairs, setPairs] = useState<TranslationPair[]>([]);
+  const [whisperModels, setWhisperModels] = useState<WhisperModel[]>([]);
+  const [importKind, setImportKind] = useState<ImportKind>("whisper");
+  const [importPath, setImportPath] = useState("");
+  const [importPair, setImportPair] = useState({
+    source: "",
+    target: "",
+    sourceTokenizer: "",
+    targetTokenizer: "",
+  });
+  const [importError, setImportError] = useState<string | null>(null);
+  const [audioDevices, setAudioDevices] = useState<AudioDeviceList | null>(null);
+  const [captureTargets, setCaptureTargets] = useState<CaptureTargetList | null>(null);
+  const [quality, setQuality] = useState<ResampleQuality | null>(null);
+  // `undefined` until loaded, `null` when recordings are off
+  const [recordingFormat, setRecordingFormat] = useState<RecordingFormat | null | undefined>(
+    undefined,
+  );
+  const [captureMode, setCaptureMode] = useState<CaptureMode | null>(null);
+  // `undefined` until loaded, `null` when the server is off
+  const [captionServer, setCaptionServer] = useState<
+    CaptionServerSettings | null | undefined
+  >(undefined);
+  const [captionPort, setCaptionPort] = useState("7789");
+  const [captionError, setCaptionError] = useState<string | null>(null);
+
+  useEffect(() => {
+    invoke<AudioDeviceList>("list_audio_devices").then(setAudioDevices);
+    invoke<CaptureTargetList>("list_capture_targets").then(setCaptureTargets);
+    invoke<ResampleQuality>("get_resample_quality").then(setQuality);
+    invoke<RecordingFormat | null>("get_recording_format").then(setRecordingFormat);
+    invoke<CaptureMode>("get_capture_mode").then(setCaptureMode);
+    invoke<CaptionServerSettings | null>("get_caption_server").then((settings) => {
+      setCaptionServer(settings);
+      if (settings) setCaptionPort(`${settings.port}`);
+    });
+  }, []);
+
+  useEffect(() => {
+    invoke<TranslationPair[]>("list_translation_pairs").then(setPairs);
+    invoke<WhisperModel[]>("list_whisper_models").then(setWhisperModels);
+  }, [modelValues]);
+
+  useEffect(() => {
+    invoke<DeviceStatus>("get_compute_device").then(setDevice);
+    const unlisten = listen<DeviceStatus>("compute-device", (event) => {
+      setDevice(event.payload);
+    });
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, []);
+
+  const handlePairChange = async (id: string) => {
+    await invoke("set_translation_pair", { id });
+    setPairs(await invoke<TranslationPair[]>("list_translation_pairs"));
+  };
+
+  // Models that aren't downloaded yet are downloaded first, the current one
+  // stays in use meanwhile
+  const handleWhisperModelChange = async (fileName: string) => {
+    const model = whisperModels.find((m) => m.fileName === fileName);
+    if (!model) return;
+    if (!model.downloaded) {
+      addModel({
+        name: `转录模型 (${whisperModelLabel(model)})`,
+        fileName,
+        description: `whisper ggml ${fileName.replace(/^ggml-|\.bin$/g, "")}`,
+      });
+      if (!(await downloadModel(fileName))) return;
+    }
+    await invoke("set_whisper_model", { fileName });
+    setWhisperModels(await invoke<WhisperModel[]>("list_whisper_models"));
+  };
+
+  const handleAudioDeviceChange = async (id: string) => {
+    await invoke("select_audio_device", { id: id || null });
+    setAudioDevices(await invoke<AudioDeviceList>("list_audio_devices"));
+  };
+
+  const handleMicDeviceChange = async (id: string) => {
+    await invoke("select_mic_device", { id: id || null });
+    setAudioDevices(await invoke<AudioDeviceList>("list_audio_devices"));
+  };
+
+  const handleCaptureTargetChange = async (id: string) => {
+    await invoke("select_capture_target", { id: id || null });
+    setCaptureTargets(await invoke<CaptureTargetList>("list_capture_targets"));
+  };
+
+  const handleCaptureModeChange = async (mode: CaptureMode) => {
+    await invoke("set_capture_mode", { mode });
+    setCaptureMode(mode);
+  };
+
+  const handleRecordingFormatChange = async (value: string) => {
+    const format = (value || null) as RecordingFormat | null;
+    await invoke("set_recording_format", { format });
+    setRecordingFormat(format);
+  };
+
+  const handleCaptionServerChange = async (enabled: boolean) => {
+    setCaptionError(null);
+    try {
+      setCaptionServer(
+        await invoke<CaptionServerSettings | null>("set_caption_server", {
+          enabled,
+          port: Number(captionPort) || null,
+          // Keeps the token when only the port changes
+          token: captionServer?.token ?? null,
+        }),
+      );
+    } catch (error) {
+      setCaptionError(`${error}`);
+    }
+  };
+
+  const handleQualityChange = async (quality: ResampleQuality) => {
+    await invoke("set_resample_quality", { quality });
+    setQuality(quality);
+  };
+
+  const handleImport = async () => {
+    setImportError(null);
+    try {
+      await invoke("import_model", {
+        path: importPath,
+        translation: importKind === "translation" ? importPair : null,
+      });
+      setImportPath("");
+      await verifyAndSyncModels();
+    } catch (error) {
+      setImportError(`${error}`);
+    }
+  };
+
+  const handleDeviceChange = async (preference: DevicePreference) => {
+    setDevice(await invoke<DeviceStatus>("set_device_preference", { preference }));
+  };
 
   // Verify models when settings page opens
   useEffect(() => {
@@ -55,14 +264,293 @@ function Settings() {
                 <span className="progress-text">
                   {model.progress.toFixed(2)}%
                 </span>
+                <button
+                  className="model-action-button"
+                  onClick={() => cancelDownload(model.fileName)}
+                >
+                  取消
+                </button>
               </div>
             )}
             {model.status === "completed" && (
-              <div className="check-mark">✓</div>
+              <>
+                <div className="check-mark">✓</div>
+                <button
+                  className="model-action-button"
+                  onClick={() => deleteModel(model.fileName)}
+                >
+                  删除
+                </button>
+              </>
             )}
           </div>
         </div>
       ))}
+      <div className="model-item import-model">
+        <div className="model-info">
+          <h3>导入本地模型</h3>
+          <p className="model-description">
+            {importKind === "whisper"
+              ? "ggml 格式的 whisper 模型文件"
+              : "Marian safetensors 文件或目录，以及两个 tokenizer.json"}
+          </p>
+          {importError && <p className="error-message">{importError}</p>}
+        </div>
+        <div className="import-fields">
+          <select
+            className="device-select"
+            value={importKind}
+            onChange={(e) => setImportKind(e.target.value as ImportKind)}
+          >
+            <option value="whisper">转录</option>
+            <option value="translation">翻译</option>
+          </select>
+          <input
+            className="import-input"
+            placeholder="模型路径"
+            value={importPath}
+            onChange={(e) => setImportPath(e.target.value)}
+          />
+          {importKind === "translation" &&
+            (
+              [
+                ["source", "源语言，例如 en"],
+                ["target", "目标语言，例如 zh"],
+                ["sourceTokenizer", "源语言 tokenizer 路径"],
+                ["targetTokenizer", "目标语言 tokenizer 路径"],
+              ] as const
+            ).map(([key, placeholder]) => (
+              <input
+                key={key}
+                className="import-input"
+                placeholder={placeholder}
+                value={importPair[key]}
+                onChange={(e) => setImportPair({ ...importPair, [key]: e.target.value })}
+              />
+            ))}
+          <button
+            className="download-button"
+            disabled={!importPath}
+            onClick={handleImport}
+          >
+            导入
+          </button>
+        </div>
+      </div>
+      {whisperModels.length > 0 && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>转录模型大小</h3>
+            <p className="model-description">越大越准确，但更慢、占用更多内存</p>
+          </div>
+          <select
+            className="device-select"
+            value={whisperModels.find((model) => model.active)?.fileName}
+            onChange={(e) => handleWhisperModelChange(e.target.value)}
+          >
+            {whisperModels.map((model) => (
+              <option key={model.fileName} value={model.fileName}>
+                {whisperModelLabel(model)}
+                {model.downloaded ? "" : ` (${model.sizeMb}MB)`}
+              </option>
+            ))}
+          </select>
+        </div>
+      )}
+      {pairs.length > 0 && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>翻译语言</h3>
+            <p className="model-description">需要先下载对应的翻译模型</p>
+          </div>
+          <select
+            className="device-select"
+            value={pairs.find((pair) => pair.active)?.id}
+            onChange={(e) => handlePairChange(e.target.value)}
+          >
+            {pairs.map((pair) => (
+              <option key={pair.id} value={pair.id} disabled={!pair.downloaded}>
+                {pair.source} → {pair.target}
+              </option>
+            ))}
+          </select>
+        </div>
+      )}
+      {audioDevices && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>音频设备</h3>
+          </div>
+          <select
+            className="device-select"
+            value={audioDevices.selected ?? ""}
+            onChange={(e) => handleAudioDeviceChange(e.target.value)}
+          >
+            <option value="">跟随系统</option>
+            {audioDevices.devices.map((d) => (
+              <option key={d.id} value={d.id}>
+                {d.kind === "input" ? "🎤 " : ""}
+                {d.name}
+              </option>
+            ))}
+          </select>
+        </div>
+      )}
+      {captureTargets && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>录制应用</h3>
+            <p className="model-description">只录制所选应用的声音，例如会议软件或浏览器</p>
+          </div>
+          <select
+            className="device-select"
+            value={captureTargets.selected ?? ""}
+            onChange={(e) => handleCaptureTargetChange(e.target.value)}
+          >
+            <option value="">全部应用</option>
+            {captureTargets.selected &&
+              !captureTargets.targets.some((t) => t.id === captureTargets.selected) && (
+                <option value={captureTargets.selected}>
+                  {captureTargets.selected.replace(/^app:/, "")} (未运行)
+                </option>
+              )}
+            {captureTargets.targets.map((t) => (
+              <option key={t.id} value={t.id}>
+                {t.name}
+              </option>
+            ))}
+          </select>
+        </div>
+      )}
+      {captureMode && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>录制内容</h3>
+            <p className="model-description">分轨时分别转录麦克风和系统声音</p>
+          </div>
+          <select
+            className="device-select"
+            value={captureMode}
+            onChange={(e) => handleCaptureModeChange(e.target.value as CaptureMode)}
+          >
+            <option value="system">系统声音</option>
+            <option value="dual">麦克风 + 系统 (分轨)</option>
+            <option value="mixed">麦克风 + 系统 (混合)</option>
+          </select>
+        </div>
+      )}
+      {captureMode && captureMode !== "system" && audioDevices &&
+        audioDevices.devices.some((d) => d.kind === "input") && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>麦克风</h3>
+          </div>
+          <select
+            className="device-select"
+            value={audioDevices.mic ?? ""}
+            onChange={(e) => handleMicDeviceChange(e.target.value)}
+          >
+            <option value="">跟随系统</option>
+            {audioDevices.devices
+              .filter((d) => d.kind === "input")
+              .map((d) => (
+                <option key={d.id} value={d.id}>
+                  {d.name}
+                </option>
+              ))}
+          </select>
+        </div>
+      )}
+      {recordingFormat !== undefined && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>保存录音</h3>
+            <p className="model-description">保存原始音频以便回放或用更好的模型重新转录</p>
+          </div>
+          <select
+            className="device-select"
+            value={recordingFormat ?? ""}
+            onChange={(e) => handleRecordingFormatChange(e.target.value)}
+          >
+            <option value="">不保存</option>
+            <option value="opus">Opus (较小)</option>
+            <option value="wav">WAV (无损)</option>
+          </select>
+        </div>
+      )}
+      {captionServer !== undefined && (
+        <div className="model-item import-model">
+          <div className="model-info">
+            <h3>字幕服务</h3>
+            <p className="model-description">
+              {captionServer
+                ? `OBS 浏览器源: http://127.0.0.1:${captionServer.port}/?token=${captionServer.token}，WebSocket: /ws`
+                : "在本机提供字幕，供 OBS 等其他程序使用"}
+            </p>
+            {captionError && <p className="error-message">{captionError}</p>}
+          </div>
+          <div className="import-fields">
+            <select
+              className="device-select"
+              value={captionServer ? "on" : "off"}
+              onChange={(e) => handleCaptionServerChange(e.target.value === "on")}
+            >
+              <option value="off">关闭</option>
+              <option value="on">开启</option>
+            </select>
+            <input
+              className="import-input"
+              placeholder="端口"
+              value={captionPort}
+              onChange={(e) => setCaptionPort(e.target.value)}
+              onBlur={() =>
+                captionServer &&
+                Number(captionPort) !== captionServer.port &&
+                handleCaptionServerChange(true)
+              }
+            />
+          </div>
+        </div>
+      )}
+      {quality && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>重采样质量</h3>
+            <p className="model-description">质量越高 CPU 占用越多</p>
+          </div>
+          <select
+            className="device-select"
+            value={quality}
+            onChange={(e) => handleQualityChange(e.target.value as ResampleQuality)}
+          >
+            <option value="best">最佳</option>
+            <option value="medium">中等</option>
+            <option value="fastest">快速</option>
+            <option value="linear">线性</option>
+          </select>
+        </div>
+      )}
+      {device && (
+        <div className="model-item">
+          <div className="model-info">
+            <h3>运行设备</h3>
+            <p className="model-description">
+              转录: {device.whisper ?? "-"} / 翻译: {device.translator ?? "-"}
+            </p>
+          </div>
+          <select
+            className="device-select"
+            value={device.preference}
+            onChange={(e) =>
+              handleDeviceChange(e.target.value as DevicePreference)
+            }
+          >
+            <option value="auto">自动</option>
+            <option value="gpu">GPU</option>
+            <option value="cpu">CPU</option>
+          </select>
+        </div>
+      )}
     </div>
   );
 }
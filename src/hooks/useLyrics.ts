@@ -4,6 +4,7 @@ import { listen } from '@tauri-apps/api/event';
 import { invoke } from '@tauri-apps/api/core';
 import {
     originalTextAtom,
+    tentativeTextAtom,
     translatedTextAtom,
     isPinnedAtom,
     isHoveredAtom,
@@ -16,8 +17,21 @@ type LyricsEvent = {
     translatedText: string;
 };
 
+type TranscriptEvent = {
+    segmentId: number;
+    sentenceId: number;
+    startMs: number;
+    endMs: number;
+    originalText: string;
+    committedText: string;
+    tentativeText: string;
+    translatedText: string;
+    language: string;
+};
+
 export function useLyrics() {
     const [originalText, setOriginalText] = useAtom(originalTextAtom);
+    const [tentativeText, setTentativeText] = useAtom(tentativeTextAtom);
     const [translatedText, setTranslatedText] = useAtom(translatedTextAtom);
     const [isPinned, setIsPinned] = useAtom(isPinnedAtom);
     const [isHovered, setIsHovered] = useAtom(isHoveredAtom);
@@ -31,13 +45,29 @@ export function useLyrics() {
         const unlisten = listen<LyricsEvent>("event", (event) => {
             const { originalText, translatedText } = event.payload;
             setOriginalText(originalText);
+            setTentativeText("");
             setTranslatedText(translatedText);
         });
 
+        const onTranscript = (event: { payload: TranscriptEvent }) => {
+            const { committedText, tentativeText } = event.payload;
+            setOriginalText(committedText);
+            setTentativeText(tentativeText);
+        };
+        const unlistenPartial = listen<TranscriptEvent>("partial", onTranscript);
+        // Only complete sentences are translated, the last translation stays
+        // up while the next sentence is spoken
+        const unlistenFinal = listen<TranscriptEvent>("final", (event) => {
+            onTranscript(event);
+            setTranslatedText(event.payload.translatedText);
+        });
+
         return () => {
             unlisten.then((f) => f());
+            unlistenPartial.then((f) => f());
+            unlistenFinal.then((f) => f());
         };
-    }, [setOriginalText, setTranslatedText]);
+    }, [setOriginalText, setTentativeText, setTranslatedText]);
 
     // Pin/unpin window
     const handlePin = useCallback(async () => {
@@ -49,6 +79,7 @@ export function useLyrics() {
         if (isRecording) {
             await invoke("stop_recording");
             setOriginalText("");
+            setTentativeText("");
             setTranslatedText("");
             setIsRecording(false);
         } else {
@@ -56,7 +87,7 @@ export function useLyrics() {
                 setIsRecording(true);
             }
         }
-    }, [isRecording, setIsRecording, setOriginalText, setTranslatedText]);
+    }, [isRecording, setIsRecording, setOriginalText, setTentativeText, setTranslatedText]);
 
     // Toggle history window
     const handleHistoryOpen = async () => {
@@ -75,6 +106,7 @@ export function useLyrics() {
     return {
         // State
         originalText,
+        tentativeText,
         translatedText,
         isPinned,
         isHovered,
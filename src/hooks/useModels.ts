@@ -9,6 +9,7 @@ import {
     setModelProgressAtom,
     updateModelAtom,
     defaultModels,
+    type ModelInfo,
     type ModelsRecord
 } from '../store/atoms';
 
@@ -18,6 +19,9 @@ export function useModels() {
     const updateModel = useSetAtom(updateModelAtom);
     const isListeningRef = useRef(false);
     const unlistenRef = useRef<(() => void) | null>(null);
+    // Read by the progress listener, which outlives the render it was created in
+    const modelsRef = useRef(models);
+    modelsRef.current = models;
 
     // Listen to download progress events
     const listenDownloadProgress = async () => {
@@ -41,7 +45,7 @@ export function useModels() {
                         const currentModelsFromStorage = await store.get<ModelsRecord>("models");
                         if (currentModelsFromStorage) {
                             // Get the current model info from models state (which has all the complete info)
-                            const currentModelInfo = models[fileName];
+                            const currentModelInfo = modelsRef.current[fileName];
                             const updatedModels = {
                                 ...currentModelsFromStorage,
                                 [fileName]: {
@@ -102,7 +106,17 @@ export function useModels() {
         verifyAndSyncModels();
     }, [verifyAndSyncModels]);
 
-    // Download model function
+    // Adds a model that isn't in the default list, e.g. another whisper size
+    const addModel = (info: Omit<ModelInfo, "status" | "progress">) => {
+        if (!models[info.fileName]) {
+            const model: ModelInfo = { ...info, status: "idle", progress: 0 };
+            modelsRef.current = { ...modelsRef.current, [info.fileName]: model };
+            updateModel(info.fileName, model);
+        }
+    };
+
+    // Download model function, returns whether the model was downloaded. The
+    // backend looks up where to download it from in its model registry
     const downloadModel = async (fileName: string) => {
         try {
             // Start listening to download progress when download begins
@@ -115,16 +129,19 @@ export function useModels() {
             });
 
             // Start the download
-            await invoke("download_model", {
-                url: models[fileName].url,
-                fileName
-            });
+            await invoke("download_model", { fileName });
+            return true;
         } catch (error) {
-            logError(`Download error: ${error}`);
-            updateModel(fileName, {
-                status: "error",
-                error: "下载失败，请重试",
-            });
+            if (`${error}`.includes("cancelled")) {
+                // What was downloaded is kept, downloading again resumes it
+                updateModel(fileName, { status: "idle", error: undefined });
+            } else {
+                logError(`Download error: ${error}`);
+                updateModel(fileName, {
+                    status: "error",
+                    error: "下载失败，请重试",
+                });
+            }
 
             // Unlisten on error
             if (unlistenRef.current) {
@@ -132,12 +149,38 @@ export function useModels() {
                 unlistenRef.current = null;
                 isListeningRef.current = false;
             }
+            return false;
+        }
+    };
+
+    const cancelDownload = async (fileName: string) => {
+        try {
+            await invoke("cancel_download", { fileName });
+        } catch (error) {
+            logError(`Cancel download error: ${error}`);
+        }
+    };
+
+    const deleteModel = async (fileName: string) => {
+        try {
+            await invoke("delete_model", { fileName });
+            updateModel(fileName, {
+                status: "idle",
+                progress: 0,
+                error: undefined,
+            });
+        } catch (error) {
+            logError(`Delete model error: ${error}`);
+            updateModel(fileName, { error: `删除失败: ${error}` });
         }
     };
 
     return {
         models,
+        addModel,
         downloadModel,
+        cancelDownload,
+        deleteModel,
         verifyAndSyncModels,
     };
 } 
\ No newline at end of file
@@ -7,7 +7,6 @@ export interface ModelInfo {
     fileName: string;
     description: string;
     status: "idle" | "downloading" | "completed" | "error";
-    url: string;
     progress: number;
     error?: string;
 }
@@ -22,7 +21,6 @@ export const defaultModels: ModelsRecord = {
         description: "whisper ggml base-q5_1",
         status: "idle",
         progress: 0,
-        url: "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-base-q5_1.bin",
     },
     "opus-mt-en-zh.bin": {
         name: "翻译模型",
@@ -30,7 +28,34 @@ export const defaultModels: ModelsRecord = {
         description: "opus-mt-en-zh",
         status: "idle",
         progress: 0,
-        url: "https://huggingface.co/Helsinki-NLP/opus-mt-en-zh/resolve/refs%2Fpr%2F26/model.safetensors",
+    },
+    "opus-mt-fr-en.bin": {
+        name: "翻译模型 (法→英)",
+        fileName: "opus-mt-fr-en.bin",
+        description: "opus-mt-fr-en",
+        status: "idle",
+        progress: 0,
+    },
+    "opus-mt-en-de.bin": {
+        name: "翻译模型 (英→德)",
+        fileName: "opus-mt-en-de.bin",
+        description: "opus-mt-en-de",
+        status: "idle",
+        progress: 0,
+    },
+    "opus-tatoeba-en-ja.bin": {
+        name: "翻译模型 (英→日)",
+        fileName: "opus-tatoeba-en-ja.bin",
+        description: "opus-tatoeba-en-ja",
+        status: "idle",
+        progress: 0,
+    },
+    "opus-mt-zh-en.bin": {
+        name: "翻译模型 (中→英)",
+        fileName: "opus-mt-zh-en.bin",
+        description: "opus-mt-zh-en",
+        status: "idle",
+        progress: 0,
     },
 };
 
@@ -39,6 +64,8 @@ export const modelsAtom = atomWithStorage<ModelsRecord>('models', defaultModels)
 
 // Text display atoms
 export const originalTextAtom = atom<string>('');
+// Tail of the current segment that may still change
+export const tentativeTextAtom = atom<string>('');
 export const translatedTextAtom = atom<string>('');
 
 // UI state atoms